@@ -0,0 +1,73 @@
+/*!
+The JSON archive format used by `--export` and `--import` to move clip
+history between machines.
+
+An archive is an array of clips, each with its number, which tier it was
+in, its file's modification time, all of its index metadata, and its
+contents. Text clips are stored as text; anything else is base64-encoded.
+Archives are never encrypted, even if the clips they came from were.
+*/
+use camino::Utf8Path;
+use serde::{Deserialize, Serialize};
+
+use crate::index::ClipMeta;
+use crate::mime;
+use crate::transform::{base64_decode, base64_encode};
+
+#[derive(Serialize, Deserialize)]
+pub struct ArchivedClip {
+    // The clip's number on the machine it was exported from.
+    pub n: usize,
+    // Whether it was in persistent storage.
+    #[serde(default)]
+    pub kept: bool,
+    // Modification time of the clip file (seconds since the Unix epoch).
+    #[serde(default)]
+    pub modified: u64,
+    #[serde(flatten)]
+    pub meta: ClipMeta,
+    // "utf-8" or "base64".
+    pub encoding: String,
+    pub data: String,
+}
+
+impl ArchivedClip {
+    pub fn new(n: usize, kept: bool, modified: u64, meta: &ClipMeta, contents: &[u8]) -> ArchivedClip {
+        let (encoding, data) = match std::str::from_utf8(contents) {
+            Ok(text) if mime::is_text(&meta.mime) => ("utf-8", text.to_owned()),
+            _ => ("base64", base64_encode(contents)),
+        };
+        ArchivedClip {
+            n,
+            kept,
+            modified,
+            meta: meta.clone(),
+            encoding: encoding.to_owned(),
+            data,
+        }
+    }
+
+    // Return the clip's contents.
+    pub fn contents(&self) -> Result<Vec<u8>, String> {
+        match self.encoding.as_str() {
+            "utf-8" => Ok(self.data.as_bytes().to_vec()),
+            "base64" => base64_decode(&self.data)
+                .map_err(|e| format!("Clip {} has bad base64 data: {}", self.n, &e)),
+            x => Err(format!("Clip {} has unknown encoding \"{}\".", self.n, x)),
+        }
+    }
+}
+
+pub fn write(path: &Utf8Path, clips: &[ArchivedClip]) -> Result<(), String> {
+    let bytes = serde_json::to_vec_pretty(clips)
+        .map_err(|e| format!("Error serializing archive: {}", &e))?;
+    // The clips may well include secrets.
+    crate::crypt::write_private(path, &bytes)
+}
+
+pub fn read(path: &Utf8Path) -> Result<Vec<ArchivedClip>, String> {
+    let bytes = std::fs::read(path)
+        .map_err(|e| format!("Unable to read archive \"{}\": {}", path, &e))?;
+    serde_json::from_slice(&bytes)
+        .map_err(|e| format!("Unable to parse archive \"{}\": {}", path, &e))
+}
@@ -0,0 +1,523 @@
+/*!
+Clipboard backends for `dmxcm`.
+
+Each backend drives an external program to read and write the clipboard.
+Which one gets used is set by the `backend = ` option in `dmxcm.toml`; by
+default it is detected from the environment.
+*/
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use camino::Utf8PathBuf;
+use serde::{Deserialize, Serialize};
+
+use crate::mime;
+
+/*
+One of the X selections. Wayland only has the first two.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Selection {
+    Primary,
+    #[default]
+    Clipboard,
+    Secondary,
+}
+
+impl Selection {
+    pub fn from_name(name: &str) -> Result<Selection, String> {
+        match name {
+            "primary" => Ok(Selection::Primary),
+            "clipboard" => Ok(Selection::Clipboard),
+            "secondary" => Ok(Selection::Secondary),
+            _ => Err(format!(
+                "Unknown selection \"{}\"; expected one of \"primary\", \"clipboard\", or \"secondary\".",
+                name
+            )),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Selection::Primary => "primary",
+            Selection::Clipboard => "clipboard",
+            Selection::Secondary => "secondary",
+        }
+    }
+}
+
+/*
+A value read from (or to be written to) a selection, along with its
+content type.
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Clip {
+    pub mime: String,
+    pub bytes: Vec<u8>,
+}
+
+/*
+Something that can read from and write to the system clipboard.
+
+Backends that can't work with specific targets only need to implement
+the text operations.
+*/
+pub trait Clipboard {
+    // Short name of the backend, for error messages.
+    fn name(&self) -> &'static str;
+    // Return the current (text) contents of the given selection.
+    fn read(&self, sel: Selection) -> Result<Vec<u8>, String>;
+    // Replace the contents of the given selection with the text `bytes`.
+    fn write(&self, sel: Selection, bytes: &[u8]) -> Result<(), String>;
+
+    // Return the targets the owner of the given selection offers.
+    fn targets(&self, _sel: Selection) -> Result<Vec<String>, String> {
+        Ok(Vec::new())
+    }
+    // Return the contents of the given selection as `target`.
+    fn read_as(&self, _sel: Selection, target: &str) -> Result<Vec<u8>, String> {
+        Err(format!("The {} backend can't read {} content.", self.name(), target))
+    }
+    // Replace the contents of the given selection with `bytes` of type `target`.
+    fn write_as(&self, _sel: Selection, target: &str, _bytes: &[u8]) -> Result<(), String> {
+        Err(format!("The {} backend can't write {} content.", self.name(), target))
+    }
+}
+
+/*
+Targets password managers offer to mark what they've copied as secret.
+KeePassXC (among others) uses KDE's hint, whose value must be "secret";
+for the rest, being offered at all is enough.
+*/
+const KDE_PASSWORD_HINT: &str = "x-kde-passwordManagerHint";
+const CONCEALED_TARGETS: &[&str] = &[
+    "application/x-nspasteboard-concealed-type",
+    "ExcludeClipboardContentFromMonitorProcessing",
+];
+
+/*
+Return whether the owner of the given selection, which offers the
+targets `offered`, has marked its contents as secret.
+*/
+pub fn is_concealed(cb: &dyn Clipboard, sel: Selection, offered: &[String]) -> bool {
+    if offered.iter().any(|t| CONCEALED_TARGETS.contains(&t.as_str())) {
+        return true;
+    }
+    if offered.iter().any(|t| t == KDE_PASSWORD_HINT) {
+        return match cb.read_as(sel, KDE_PASSWORD_HINT) {
+            Ok(hint) => hint.trim_ascii() == b"secret",
+            // If we can't tell, err on the side of caution.
+            Err(_) => true,
+        };
+    }
+    false
+}
+
+/*
+Read the given selection as the first of the content types in `prefs` on
+offer, falling back to text. Returns `None` if the selection's owner has
+marked it as secret.
+
+Backends that can't list targets (like xsel) can't detect secrets.
+*/
+pub fn read_preferred(
+    cb: &dyn Clipboard,
+    sel: Selection,
+    prefs: &[String]
+) -> Result<Option<Clip>, String> {
+    let offered = cb.targets(sel)?;
+    if is_concealed(cb, sel, &offered) {
+        return Ok(None);
+    }
+    let mime = mime::choose_target(prefs, &offered);
+    let bytes = if mime == mime::TEXT_PLAIN {
+        cb.read(sel)?
+    } else {
+        cb.read_as(sel, mime)?
+    };
+    Ok(Some(Clip { mime: mime.to_owned(), bytes }))
+}
+
+/*
+Put `clip` into the given selection under its original content type.
+*/
+pub fn write_clip(cb: &dyn Clipboard, sel: Selection, clip: &Clip) -> Result<(), String> {
+    if clip.mime == mime::TEXT_PLAIN {
+        cb.write(sel, &clip.bytes)
+    } else {
+        cb.write_as(sel, &clip.mime, &clip.bytes)
+    }
+}
+
+/*
+Split program output into a list of targets, one per line.
+*/
+fn target_list(output: Vec<u8>) -> Vec<String> {
+    String::from_utf8_lossy(&output)
+        .lines()
+        .map(|line| line.trim().to_owned())
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/*
+The value of the `backend = ` configuration option.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Auto,
+    Xclip,
+    Xsel,
+    WlClipboard,
+}
+
+impl Backend {
+    pub fn from_name(name: &str) -> Result<Backend, String> {
+        match name {
+            "auto" => Ok(Backend::Auto),
+            "xclip" => Ok(Backend::Xclip),
+            "xsel" => Ok(Backend::Xsel),
+            "wl-clipboard" | "wayland" => Ok(Backend::WlClipboard),
+            _ => Err(format!(
+                "Unknown clipboard backend \"{}\"; expected one of \"auto\", \"xclip\", \"xsel\", or \"wl-clipboard\".",
+                name
+            )),
+        }
+    }
+
+    /*
+    Resolve `Backend::Auto` to a concrete backend based on the environment:
+    wl-clipboard under Wayland, xclip otherwise.
+    */
+    pub fn detect(self) -> Backend {
+        if self != Backend::Auto {
+            return self;
+        }
+
+        let is_set = |var: &str| match std::env::var_os(var) {
+            Some(val) => !val.is_empty(),
+            None => false,
+        };
+
+        if is_set("WAYLAND_DISPLAY") {
+            Backend::WlClipboard
+        } else if is_set("DISPLAY") {
+            Backend::Xclip
+        } else {
+            eprintln!("Neither WAYLAND_DISPLAY nor DISPLAY is set; trying xclip.");
+            Backend::Xclip
+        }
+    }
+}
+
+/*
+Paths to the programs the various backends run.
+*/
+#[derive(Debug)]
+pub struct Programs {
+    pub xclip: Utf8PathBuf,
+    pub xsel: Utf8PathBuf,
+    pub wl_copy: Utf8PathBuf,
+    pub wl_paste: Utf8PathBuf,
+}
+
+/*
+Instantiate the clipboard backend specified by `backend`, using the
+program paths in `progs`.
+*/
+pub fn open(backend: Backend, progs: &Programs) -> Box<dyn Clipboard> {
+    match backend.detect() {
+        Backend::Xclip | Backend::Auto => Box::new(Xclip { path: progs.xclip.clone() }),
+        Backend::Xsel => Box::new(Xsel { path: progs.xsel.clone() }),
+        Backend::WlClipboard => Box::new(WlClipboard {
+            copy: progs.wl_copy.clone(),
+            paste: progs.wl_paste.clone(),
+        }),
+    }
+}
+
+/*
+Run `cmd` and return its stdout.
+
+The exit status is deliberately ignored: the clipboard programs all exit
+with an error when the clipboard is empty, which we treat as an empty
+value.
+*/
+fn output_of(name: &str, mut cmd: Command) -> Result<Vec<u8>, String> {
+    let output = cmd.stderr(Stdio::null()).output()
+        .map_err(|e| format!("Error running {} process: {}", name, &e))?
+        .stdout;
+    Ok(output)
+}
+
+/*
+Run `cmd`, writing `bytes` to its stdin.
+*/
+pub fn pipe_into(name: &str, mut cmd: Command, bytes: &[u8]) -> Result<(), String> {
+    let mut child = cmd.stdin(Stdio::piped()).spawn()
+        .map_err(|e| format!("Unable to spawn {} process: {}", name, &e))?;
+    {
+        let mut handle = child.stdin.take()
+            .ok_or(format!("{} child process stdin handle unavailable.", name))?;
+        handle.write_all(bytes)
+            .map_err(|e| format!("Error writing to {} process: {}", name, &e))?;
+    }
+    let status = child.wait()
+        .map_err(|e| format!("Error awaiting {} process: {}", name, &e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{} process returned exit code {:?}", name, &status.code()))
+    }
+}
+
+pub struct Xclip {
+    path: Utf8PathBuf,
+}
+
+impl Clipboard for Xclip {
+    fn name(&self) -> &'static str { "xclip" }
+
+    fn read(&self, sel: Selection) -> Result<Vec<u8>, String> {
+        let mut cmd = Command::new(&self.path);
+        cmd.args(["-o", "-selection", sel.name()]);
+        output_of(self.name(), cmd)
+    }
+
+    fn write(&self, sel: Selection, bytes: &[u8]) -> Result<(), String> {
+        let mut cmd = Command::new(&self.path);
+        cmd.args(["-i", "-selection", sel.name()]);
+        pipe_into(self.name(), cmd, bytes)
+    }
+
+    fn targets(&self, sel: Selection) -> Result<Vec<String>, String> {
+        self.read_as(sel, "TARGETS").map(target_list)
+    }
+
+    fn read_as(&self, sel: Selection, target: &str) -> Result<Vec<u8>, String> {
+        let mut cmd = Command::new(&self.path);
+        cmd.args(["-o", "-selection", sel.name(), "-t", target]);
+        output_of(self.name(), cmd)
+    }
+
+    fn write_as(&self, sel: Selection, target: &str, bytes: &[u8]) -> Result<(), String> {
+        let mut cmd = Command::new(&self.path);
+        cmd.args(["-i", "-selection", sel.name(), "-t", target]);
+        pipe_into(self.name(), cmd, bytes)
+    }
+}
+
+pub struct Xsel {
+    path: Utf8PathBuf,
+}
+
+impl Clipboard for Xsel {
+    fn name(&self) -> &'static str { "xsel" }
+
+    fn read(&self, sel: Selection) -> Result<Vec<u8>, String> {
+        let mut cmd = Command::new(&self.path);
+        cmd.args(["--output", &format!("--{}", sel.name())]);
+        output_of(self.name(), cmd)
+    }
+
+    fn write(&self, sel: Selection, bytes: &[u8]) -> Result<(), String> {
+        let mut cmd = Command::new(&self.path);
+        cmd.args(["--input", &format!("--{}", sel.name())]);
+        pipe_into(self.name(), cmd, bytes)
+    }
+}
+
+pub struct WlClipboard {
+    copy: Utf8PathBuf,
+    paste: Utf8PathBuf,
+}
+
+impl WlClipboard {
+    /*
+    Return a `Command` running `prog` set to operate on `sel`.
+    */
+    fn command(&self, prog: &Utf8PathBuf, sel: Selection) -> Result<Command, String> {
+        let mut cmd = Command::new(prog);
+        match sel {
+            Selection::Clipboard => {},
+            Selection::Primary => { cmd.arg("--primary"); },
+            Selection::Secondary => {
+                return Err("Wayland has no secondary selection.".to_owned());
+            },
+        }
+        Ok(cmd)
+    }
+}
+
+impl Clipboard for WlClipboard {
+    fn name(&self) -> &'static str { "wl-clipboard" }
+
+    fn read(&self, sel: Selection) -> Result<Vec<u8>, String> {
+        let mut cmd = self.command(&self.paste, sel)?;
+        cmd.arg("--no-newline");
+        output_of(self.name(), cmd)
+    }
+
+    fn write(&self, sel: Selection, bytes: &[u8]) -> Result<(), String> {
+        let cmd = self.command(&self.copy, sel)?;
+        pipe_into(self.name(), cmd, bytes)
+    }
+
+    fn targets(&self, sel: Selection) -> Result<Vec<String>, String> {
+        let mut cmd = self.command(&self.paste, sel)?;
+        cmd.arg("--list-types");
+        output_of(self.name(), cmd).map(target_list)
+    }
+
+    fn read_as(&self, sel: Selection, target: &str) -> Result<Vec<u8>, String> {
+        let mut cmd = self.command(&self.paste, sel)?;
+        cmd.args(["--no-newline", "--type", target]);
+        output_of(self.name(), cmd)
+    }
+
+    fn write_as(&self, sel: Selection, target: &str, bytes: &[u8]) -> Result<(), String> {
+        let mut cmd = self.command(&self.copy, sel)?;
+        cmd.args(["--type", target]);
+        pipe_into(self.name(), cmd, bytes)
+    }
+}
+
+/*
+A clipboard for tests: its contents are whatever was last `offer()`ed,
+and everything written to it is recorded. It has only the one selection.
+*/
+#[cfg(test)]
+pub mod mock {
+    use std::cell::RefCell;
+
+    use super::{Clipboard, Selection};
+    use crate::mime;
+
+    #[derive(Default)]
+    pub struct Mock {
+        // The targets on offer, with their contents.
+        offered: RefCell<Vec<(String, Vec<u8>)>>,
+        // What's been written, as (selection, target, contents).
+        pub written: RefCell<Vec<(Selection, String, Vec<u8>)>>,
+    }
+
+    impl Mock {
+        // Replace the contents with `targets`, as (target, contents) pairs.
+        pub fn offer(&self, targets: &[(&str, &[u8])]) {
+            *self.offered.borrow_mut() = targets.iter()
+                .map(|(t, bytes)| (t.to_string(), bytes.to_vec()))
+                .collect();
+        }
+
+        // Replace the contents with `text`, the way X clients offer text.
+        pub fn set_text(&self, text: &str) {
+            self.offer(&[("TARGETS", b""), ("UTF8_STRING", text.as_bytes())]);
+        }
+    }
+
+    impl Clipboard for Mock {
+        fn name(&self) -> &'static str { "mock" }
+
+        fn read(&self, sel: Selection) -> Result<Vec<u8>, String> {
+            Ok(self.read_as(sel, "UTF8_STRING").unwrap_or_default())
+        }
+
+        fn write(&self, sel: Selection, bytes: &[u8]) -> Result<(), String> {
+            self.written.borrow_mut().push((sel, mime::TEXT_PLAIN.to_owned(), bytes.to_vec()));
+            Ok(())
+        }
+
+        fn targets(&self, _sel: Selection) -> Result<Vec<String>, String> {
+            Ok(self.offered.borrow().iter().map(|(t, _)| t.clone()).collect())
+        }
+
+        fn read_as(&self, _sel: Selection, target: &str) -> Result<Vec<u8>, String> {
+            self.offered.borrow().iter()
+                .find(|(t, _)| t == target)
+                .map(|(_, bytes)| bytes.clone())
+                .ok_or_else(|| format!("{} isn't on offer.", target))
+        }
+
+        fn write_as(&self, sel: Selection, target: &str, bytes: &[u8]) -> Result<(), String> {
+            self.written.borrow_mut().push((sel, target.to_owned(), bytes.to_vec()));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock::Mock;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\nfake";
+
+    fn prefs(targets: &[&str]) -> Vec<String> {
+        targets.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn read_preferred_takes_first_preference_on_offer() {
+        let cb = Mock::default();
+        cb.offer(&[("TARGETS", b""), ("UTF8_STRING", b"text"), ("image/png", PNG)]);
+        let sel = Selection::Clipboard;
+
+        let clip = read_preferred(&cb, sel, &prefs(&["image/png", "text/plain"])).unwrap();
+        assert_eq!(clip, Some(Clip { mime: "image/png".to_owned(), bytes: PNG.to_vec() }));
+        let clip = read_preferred(&cb, sel, &prefs(&["text/html", "text/plain"])).unwrap();
+        assert_eq!(clip, Some(Clip { mime: "text/plain".to_owned(), bytes: b"text".to_vec() }));
+    }
+
+    #[test]
+    fn read_preferred_falls_back_to_text() {
+        let cb = Mock::default();
+        cb.set_text("text");
+        let clip = read_preferred(&cb, Selection::Primary, &prefs(&["image/png"])).unwrap();
+        assert_eq!(clip, Some(Clip { mime: "text/plain".to_owned(), bytes: b"text".to_vec() }));
+    }
+
+    #[test]
+    fn read_preferred_skips_concealed_values() {
+        let cb = Mock::default();
+        cb.offer(&[("UTF8_STRING", b"hunter2"), ("x-kde-passwordManagerHint", b"secret")]);
+        let clip = read_preferred(&cb, Selection::Clipboard, &prefs(&["text/plain"])).unwrap();
+        assert_eq!(clip, None);
+    }
+
+    #[test]
+    fn concealment_markers() {
+        let cb = Mock::default();
+        let sel = Selection::Clipboard;
+        let offered = |cb: &Mock| cb.targets(sel).unwrap();
+
+        cb.set_text("plain");
+        assert!(!is_concealed(&cb, sel, &offered(&cb)));
+        cb.offer(&[("UTF8_STRING", b"x"), ("application/x-nspasteboard-concealed-type", b"")]);
+        assert!(is_concealed(&cb, sel, &offered(&cb)));
+        cb.offer(&[("UTF8_STRING", b"x"), ("ExcludeClipboardContentFromMonitorProcessing", b"")]);
+        assert!(is_concealed(&cb, sel, &offered(&cb)));
+        cb.offer(&[("UTF8_STRING", b"x"), ("x-kde-passwordManagerHint", b"secret\n")]);
+        assert!(is_concealed(&cb, sel, &offered(&cb)));
+        cb.offer(&[("UTF8_STRING", b"x"), ("x-kde-passwordManagerHint", b"public")]);
+        assert!(!is_concealed(&cb, sel, &offered(&cb)));
+
+        // A hint that's listed but can't be read counts as secret.
+        cb.set_text("x");
+        let listed = vec!["UTF8_STRING".to_owned(), "x-kde-passwordManagerHint".to_owned()];
+        assert!(is_concealed(&cb, sel, &listed));
+    }
+
+    #[test]
+    fn write_clip_uses_original_type() {
+        let cb = Mock::default();
+        let text = Clip { mime: "text/plain".to_owned(), bytes: b"text".to_vec() };
+        let image = Clip { mime: "image/png".to_owned(), bytes: PNG.to_vec() };
+        write_clip(&cb, Selection::Primary, &text).unwrap();
+        write_clip(&cb, Selection::Clipboard, &image).unwrap();
+        assert_eq!(*cb.written.borrow(), [
+            (Selection::Primary, "text/plain".to_owned(), b"text".to_vec()),
+            (Selection::Clipboard, "image/png".to_owned(), PNG.to_vec()),
+        ]);
+    }
+}
@@ -0,0 +1,382 @@
+/*!
+Optional encryption of clip files at rest.
+
+Clips are encrypted with XChaCha20-Poly1305 under a key derived (with
+Argon2) from either a passphrase or the contents of a keyfile, plus a
+random salt kept in the persistent clip directory. The derived key is
+cached in the user's runtime directory so the passphrase doesn't have to
+be entered on every invocation; `dmxcm --lock` deletes the cached key.
+
+Encrypted files start with `MAGIC`, so plaintext clips left over from
+before encryption was turned on can still be read. Encrypted clips left
+over after it's turned off are skipped until `dmxcm --migrate` decrypts
+them.
+*/
+use std::fmt;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::process::Command;
+
+use argon2::Argon2;
+use camino::{Utf8Path, Utf8PathBuf};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+
+// Marks the start of an encrypted clip file.
+const MAGIC: &[u8] = b"DMXCM-ENC1\n";
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+// Name of the file (in the persistent clip directory) holding the salt.
+const PARAMS_FILE: &str = ".crypt.json";
+// Known plaintext encrypted in the params file to check keys against.
+const CHECK_TEXT: &[u8] = b"dmxcm";
+
+/*
+Where the encryption key comes from (the `encryption = ` option).
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    Off,
+    Passphrase,
+    Keyfile(Utf8PathBuf),
+}
+
+/*
+Stored in `PARAMS_FILE`.
+*/
+#[derive(Serialize, Deserialize)]
+struct Params {
+    salt: Vec<u8>,
+    // `CHECK_TEXT`, encrypted with the key.
+    check: Vec<u8>,
+}
+
+pub struct Crypt {
+    // Where the key comes from; this can be set even when encryption is
+    // off, so clips encrypted before it was turned off can be migrated.
+    mode: Mode,
+    enabled: bool,
+    // Program to ask for the passphrase with.
+    askpass: Option<Utf8PathBuf>,
+    params_path: Utf8PathBuf,
+    cache_path: Utf8PathBuf,
+    // The key, or why it couldn't be had; either way, it's only sought once.
+    key: OnceCell<Result<[u8; KEY_LEN], String>>,
+}
+
+// Written by hand so the key never ends up in debug output.
+impl fmt::Debug for Crypt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Crypt")
+            .field("mode", &self.mode)
+            .field("enabled", &self.enabled)
+            .field("askpass", &self.askpass)
+            .field("params_path", &self.params_path)
+            .field("cache_path", &self.cache_path)
+            .finish()
+    }
+}
+
+/*
+Write `bytes` to a new file at `path` readable only by the user.
+*/
+pub fn write_private(path: &Utf8Path, bytes: &[u8]) -> Result<(), String> {
+    let mut f = std::fs::OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| format!("Unable to open \"{}\" for create/truncate/write: {}", path, &e))?;
+    f.write_all(bytes)
+        .map_err(|e| format!("Error writing to \"{}\": {}", path, &e))
+}
+
+/*
+Like `write_private()`, but never overwrites an existing file.
+*/
+fn write_new_private(path: &Utf8Path, bytes: &[u8]) -> Result<(), String> {
+    let mut f = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| format!("Unable to create \"{}\": {}", path, &e))?;
+    f.write_all(bytes)
+        .map_err(|e| format!("Error writing to \"{}\": {}", path, &e))
+}
+
+/*
+Return whether `bytes` is the contents of an encrypted clip file.
+*/
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+fn seal(key: &[u8; KEY_LEN], plain: &[u8]) -> Result<Vec<u8>, String> {
+    let cipher = XChaCha20Poly1305::new(key.into());
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let sealed = cipher.encrypt(&nonce, plain)
+        .map_err(|_| "Encryption failed.".to_owned())?;
+
+    let mut out = Vec::with_capacity(MAGIC.len() + NONCE_LEN + sealed.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&sealed);
+    Ok(out)
+}
+
+fn open(key: &[u8; KEY_LEN], data: &[u8]) -> Result<Vec<u8>, String> {
+    if !is_encrypted(data) || data.len() < MAGIC.len() + NONCE_LEN {
+        return Err("Not an encrypted clip.".to_owned());
+    }
+    let (nonce, sealed) = data[MAGIC.len()..].split_at(NONCE_LEN);
+    let cipher = XChaCha20Poly1305::new(key.into());
+    cipher.decrypt(XNonce::from_slice(nonce), sealed)
+        .map_err(|_| "Unable to decrypt clip (wrong key?).".to_owned())
+}
+
+impl Crypt {
+    /*
+    `persist_dir` is where the salt is kept; `runtime_dir` is where the
+    derived key gets cached.
+    */
+    pub fn new(
+        mode: Mode,
+        askpass: Option<Utf8PathBuf>,
+        persist_dir: &Utf8Path,
+        runtime_dir: &Utf8Path
+    ) -> Crypt {
+        Crypt {
+            enabled: mode != Mode::Off,
+            mode,
+            askpass,
+            params_path: persist_dir.join(PARAMS_FILE),
+            cache_path: runtime_dir.join("dmxcm.key"),
+            key: OnceCell::new(),
+        }
+    }
+
+    /*
+    Like `new()`, but with encryption turned off: nothing new gets
+    encrypted, and the key (from `mode`) is only sought by
+    `decrypt_anyway()`.
+    */
+    pub fn disabled(
+        mode: Mode,
+        askpass: Option<Utf8PathBuf>,
+        persist_dir: &Utf8Path,
+        runtime_dir: &Utf8Path
+    ) -> Crypt {
+        Crypt {
+            enabled: false,
+            ..Crypt::new(mode, askpass, persist_dir, runtime_dir)
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /*
+    Return the secret the key is derived from: the passphrase (from the
+    askpass program) or the keyfile contents.
+    */
+    fn secret(&self) -> Result<Vec<u8>, String> {
+        match &self.mode {
+            Mode::Off => Err("Encryption is not enabled.".to_owned()),
+            Mode::Keyfile(path) => std::fs::read(path)
+                .map_err(|e| format!("Unable to read keyfile \"{}\": {}", path, &e)),
+            Mode::Passphrase => {
+                let askpass = match &self.askpass {
+                    Some(path) => path.clone(),
+                    None => match std::env::var("SSH_ASKPASS") {
+                        Ok(path) => Utf8PathBuf::from(path),
+                        Err(_) => {
+                            return Err("No askpass program configured to ask for the passphrase.".to_owned());
+                        },
+                    },
+                };
+                let mut output = Command::new(&askpass)
+                    .arg("dmxcm passphrase:")
+                    .output()
+                    .map_err(|e| format!("Error running askpass program \"{}\": {}", &askpass, &e))?;
+                if !output.status.success() {
+                    return Err("Passphrase entry cancelled.".to_owned());
+                }
+                while output.stdout.last() == Some(&b'\n') {
+                    output.stdout.pop();
+                }
+                Ok(output.stdout)
+            },
+        }
+    }
+
+    fn derive(secret: &[u8], salt: &[u8]) -> Result<[u8; KEY_LEN], String> {
+        let mut key = [0u8; KEY_LEN];
+        Argon2::default().hash_password_into(secret, salt, &mut key)
+            .map_err(|e| format!("Error deriving encryption key: {}", &e))?;
+        Ok(key)
+    }
+
+    /*
+    Derive the key from the user's secret, creating the salt (and the
+    check value) the first time, and verifying the key against the check
+    value thereafter.
+    */
+    fn unlock(&self) -> Result<[u8; KEY_LEN], String> {
+        let secret = self.secret()?;
+
+        match std::fs::read(&self.params_path) {
+            Ok(bytes) => {
+                let params: Params = serde_json::from_slice(&bytes)
+                    .map_err(|e| format!("Unable to parse \"{}\": {}", &self.params_path, &e))?;
+                let key = Crypt::derive(&secret, &params.salt)?;
+                match open(&key, &params.check) {
+                    Ok(text) if text == CHECK_TEXT => Ok(key),
+                    _ => Err("Incorrect passphrase or keyfile.".to_owned()),
+                }
+            },
+            // Only a first run (with encryption on) creates a salt; any
+            // other trouble reading it mustn't replace it.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && self.enabled() => {
+                let mut salt = vec![0u8; 16];
+                OsRng.fill_bytes(&mut salt);
+                let key = Crypt::derive(&secret, &salt)?;
+                let params = Params { salt, check: seal(&key, CHECK_TEXT)? };
+                let bytes = serde_json::to_vec(&params)
+                    .map_err(|e| format!("Error serializing encryption parameters: {}", &e))?;
+                write_new_private(&self.params_path, &bytes)?;
+                Ok(key)
+            },
+            Err(e) => Err(format!("Unable to read \"{}\": {}", &self.params_path, &e)),
+        }
+    }
+
+    /*
+    Return the key, from (in order of preference) this process, the
+    runtime cache, or the user. If the user can't supply it (say, they
+    cancel the passphrase prompt), they aren't asked again; the same error
+    is returned for the rest of the run.
+    */
+    fn key(&self) -> Result<&[u8; KEY_LEN], String> {
+        self.key.get_or_init(|| {
+            if let Ok(bytes) = std::fs::read(&self.cache_path) {
+                if let Ok(key) = <[u8; KEY_LEN]>::try_from(bytes.as_slice()) {
+                    return Ok(key);
+                }
+            }
+            let key = self.unlock()?;
+            if let Err(e) = write_private(&self.cache_path, &key) {
+                eprintln!("Unable to cache encryption key: {}", &e);
+            }
+            Ok(key)
+        }).as_ref().map_err(|e| e.clone())
+    }
+
+    /*
+    Return why the key couldn't be had, if it was sought and couldn't be.
+    */
+    pub fn key_error(&self) -> Option<&str> {
+        match self.key.get() {
+            Some(Err(e)) => Some(e),
+            _ => None,
+        }
+    }
+
+    /*
+    Encrypt `plain` for storage if encryption is enabled; otherwise
+    return it unchanged.
+    */
+    pub fn encrypt(&self, plain: &[u8]) -> Result<Vec<u8>, String> {
+        if !self.enabled() {
+            return Ok(plain.to_vec());
+        }
+        seal(self.key()?, plain)
+    }
+
+    /*
+    Decrypt the stored clip `data` if it's encrypted; otherwise return it
+    unchanged. With encryption off, encrypted clips are refused rather
+    than asking for a key.
+    */
+    pub fn decrypt(&self, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        if is_encrypted(&data) && !self.enabled() {
+            return Err("Clip is encrypted, but encryption is off (`dmxcm --migrate` decrypts it).".to_owned());
+        }
+        self.decrypt_anyway(data)
+    }
+
+    /*
+    Like `decrypt()`, but seeks the key even if encryption is off.
+    */
+    pub fn decrypt_anyway(&self, data: Vec<u8>) -> Result<Vec<u8>, String> {
+        if !is_encrypted(&data) {
+            return Ok(data);
+        }
+        open(self.key()?, &data)
+    }
+
+    /*
+    Forget the cached key. Returns whether there was one to forget.
+    */
+    pub fn lock(&self) -> Result<bool, String> {
+        match std::fs::remove_file(&self.cache_path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(format!("Unable to remove cached key \"{}\": {}", &self.cache_path, &e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_passphrase_is_only_asked_for_once() {
+        let dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("dmxcm-test-crypt-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let askpass = dir.join("askpass");
+        let count = dir.join("count");
+        let script = format!("#!/bin/sh\necho asked >> '{}'\nexit 1\n", &count);
+        std::fs::write(&askpass, script).unwrap();
+        Command::new("chmod").arg("+x").arg(&askpass).status().unwrap();
+
+        let crypt = Crypt::new(Mode::Passphrase, Some(askpass), &dir, &dir);
+        let sealed = seal(&[0u8; KEY_LEN], b"secret").unwrap();
+        for _ in 0..3 {
+            assert_eq!(crypt.decrypt(sealed.clone()), Err("Passphrase entry cancelled.".to_owned()));
+        }
+        assert_eq!(crypt.key_error(), Some("Passphrase entry cancelled."));
+        assert_eq!(std::fs::read_to_string(&count).unwrap(), "asked\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn encrypted_clips_are_refused_with_encryption_off() {
+        let dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("dmxcm-test-crypt-off-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let keyfile = dir.join("keyfile");
+        std::fs::write(&keyfile, "hunter2").unwrap();
+
+        let sealed = Crypt::new(Mode::Keyfile(keyfile.clone()), None, &dir, &dir)
+            .encrypt(b"secret")
+            .unwrap();
+        std::fs::remove_file(dir.join("dmxcm.key")).unwrap();
+
+        let crypt = Crypt::disabled(Mode::Keyfile(keyfile), None, &dir, &dir);
+        assert_eq!(crypt.encrypt(b"plain").unwrap(), b"plain");
+        assert!(crypt.decrypt(sealed.clone()).is_err());
+        assert_eq!(crypt.key_error(), None);
+        assert_eq!(crypt.decrypt_anyway(sealed).unwrap(), b"secret");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
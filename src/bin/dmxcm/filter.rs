@@ -0,0 +1,162 @@
+/*!
+Rules for refusing to save clips that look like secrets.
+
+A saved text value is checked against the user's regexes, length limits,
+and any enabled built-in detectors. If anything matches, the clip is
+either dropped or saved with a short expiry time, depending on the
+configured `Action`.
+*/
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+static JWT_RE: Lazy<Regex> = Lazy::new(|| Regex::new(
+    r"\beyJ[A-Za-z0-9_-]{5,}\.eyJ[A-Za-z0-9_-]{5,}\.[A-Za-z0-9_-]{10,}"
+).unwrap());
+static AWS_RE: Lazy<Regex> = Lazy::new(|| Regex::new(
+    r"\b(AKIA|ASIA)[0-9A-Z]{16}\b|(?i)aws_secret_access_key\s*[=:]\s*[A-Za-z0-9/+=]{40}"
+).unwrap());
+static PEM_RE: Lazy<Regex> = Lazy::new(|| Regex::new(
+    r"-----BEGIN ([A-Z0-9]+ )*PRIVATE KEY-----"
+).unwrap());
+
+// Tokens shorter than this are never considered high-entropy.
+const ENTROPY_MIN_LEN: usize = 24;
+// Bits per character at or above which a token is considered random.
+const ENTROPY_THRESHOLD: f64 = 3.5;
+
+/*
+The built-in secret detectors, enabled by name in `ignore_detectors`.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Detector {
+    Jwt,
+    Aws,
+    Pem,
+    Entropy,
+}
+
+impl Detector {
+    pub fn from_name(name: &str) -> Result<Detector, String> {
+        match name {
+            "jwt" => Ok(Detector::Jwt),
+            "aws" => Ok(Detector::Aws),
+            "pem" => Ok(Detector::Pem),
+            "entropy" => Ok(Detector::Entropy),
+            _ => Err(format!(
+                "Unknown detector \"{}\"; expected one of \"jwt\", \"aws\", \"pem\", or \"entropy\".",
+                name
+            )),
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Detector::Jwt => "looks like a JSON Web Token",
+            Detector::Aws => "looks like an AWS key",
+            Detector::Pem => "contains a PEM private key",
+            Detector::Entropy => "contains a long high-entropy string",
+        }
+    }
+
+    fn matches(&self, text: &str) -> bool {
+        match self {
+            Detector::Jwt => JWT_RE.is_match(text),
+            Detector::Aws => AWS_RE.is_match(text),
+            Detector::Pem => PEM_RE.is_match(text),
+            Detector::Entropy => text.split_whitespace().any(is_high_entropy),
+        }
+    }
+}
+
+/*
+Return the Shannon entropy of `token`, in bits per character.
+*/
+fn entropy(token: &str) -> f64 {
+    let mut counts = [0usize; 256];
+    for b in token.bytes() {
+        counts[b as usize] += 1;
+    }
+    let len = token.len() as f64;
+    counts.iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/*
+Return whether `token` looks like a random key: long, made up only of the
+characters used in hex/base64 encodings, containing both letters and
+digits, and with high entropy.
+*/
+fn is_high_entropy(token: &str) -> bool {
+    token.len() >= ENTROPY_MIN_LEN
+        && token.bytes().all(|b| b.is_ascii_alphanumeric() || b"+/=_-".contains(&b))
+        && token.bytes().any(|b| b.is_ascii_alphabetic())
+        && token.bytes().any(|b| b.is_ascii_digit())
+        && entropy(token) >= ENTROPY_THRESHOLD
+}
+
+/*
+What to do with a clip that matches a rule.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Drop,
+    // Save it, but have it expire after this many seconds.
+    Expire(u64),
+}
+
+#[derive(Debug, Clone)]
+pub struct Rules {
+    pub patterns: Vec<Regex>,
+    // In characters.
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    pub detectors: Vec<Detector>,
+    pub action: Action,
+}
+
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules {
+            patterns: Vec::new(),
+            min_length: None,
+            max_length: None,
+            detectors: Vec::new(),
+            action: Action::Drop,
+        }
+    }
+}
+
+impl Rules {
+    /*
+    Return a description of the first rule `text` matches, if any.
+    */
+    pub fn check(&self, text: &str) -> Option<String> {
+        let len = text.chars().count();
+        if let Some(min) = self.min_length {
+            if len < min {
+                return Some(format!("shorter than ignore_min_length ({})", min));
+            }
+        }
+        if let Some(max) = self.max_length {
+            if len > max {
+                return Some(format!("longer than ignore_max_length ({})", max));
+            }
+        }
+        for re in self.patterns.iter() {
+            if re.is_match(text) {
+                return Some(format!("matches pattern /{}/", re.as_str()));
+            }
+        }
+        for det in self.detectors.iter() {
+            if det.matches(text) {
+                return Some(det.description().to_owned());
+            }
+        }
+        None
+    }
+}
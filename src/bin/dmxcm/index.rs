@@ -0,0 +1,329 @@
+/*!
+The index of per-clip metadata kept alongside the clips themselves.
+
+It's stored as JSON in a hidden file in the clip directory, keyed by clip
+number. A missing or unreadable index just means no metadata is known;
+`Index::reconcile()` fills in entries for clip files it doesn't know
+about (say, ones added by hand) and `Index::retain()` drops entries whose
+files are gone, so the index rebuilds itself as clips are read.
+*/
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::clipboard::Selection;
+use crate::mime;
+
+// Name of the index file in the clip directory.
+pub const INDEX_FILE: &str = ".index.json";
+
+/*
+Return the current time in seconds since the Unix epoch.
+*/
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/*
+Return the modification time of the file at `path` in seconds since the
+Unix epoch, or the current time if it can't be determined.
+*/
+pub fn mtime(path: &Utf8Path) -> u64 {
+    std::fs::metadata(path)
+        .and_then(|md| md.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or_else(now)
+}
+
+fn default_mime() -> String {
+    mime::TEXT_PLAIN.to_owned()
+}
+
+/*
+Information about a single clip that isn't contained in its file.
+*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipMeta {
+    // The selection the clip was saved from.
+    #[serde(default)]
+    pub selection: Selection,
+    // The content type it was saved as.
+    #[serde(default = "default_mime")]
+    pub mime: String,
+    // When the clip was saved (seconds since the Unix epoch).
+    #[serde(default)]
+    pub created: u64,
+    // When the clip was last recalled, if ever.
+    #[serde(default)]
+    pub last_recalled: Option<u64>,
+    // How many times the clip has been recalled.
+    #[serde(default)]
+    pub recalls: u64,
+    // Size of the clip in bytes.
+    #[serde(default)]
+    pub size: usize,
+    // A name to show in the menu.
+    #[serde(default)]
+    pub label: Option<String>,
+    // Tags for picking out related clips, without the leading '#'.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // Pinned clips are listed first and never expunged, pruned, or expired.
+    #[serde(default)]
+    pub pinned: bool,
+    // When the clip should be deleted, if ever.
+    #[serde(default)]
+    pub expires: Option<u64>,
+}
+
+impl Default for ClipMeta {
+    fn default() -> ClipMeta {
+        ClipMeta {
+            selection: Selection::default(),
+            mime: default_mime(),
+            created: 0,
+            last_recalled: None,
+            recalls: 0,
+            size: 0,
+            label: None,
+            tags: Vec::new(),
+            pinned: false,
+            expires: None,
+        }
+    }
+}
+
+impl ClipMeta {
+    /*
+    Metadata for a clip of `size` bytes being saved right now.
+    */
+    pub fn new(selection: Selection, mime: &str, size: usize) -> ClipMeta {
+        ClipMeta {
+            selection,
+            mime: mime.to_owned(),
+            created: now(),
+            size,
+            ..Default::default()
+        }
+    }
+
+    // Pinned clips never expire, whatever `expires` says.
+    pub fn is_expired(&self, now: u64) -> bool {
+        !self.pinned && matches!(self.expires, Some(t) if t <= now)
+    }
+
+    // Note that the clip has just been recalled.
+    pub fn record_recall(&mut self) {
+        self.recalls += 1;
+        self.last_recalled = Some(now());
+    }
+
+    // When the clip was last saved or recalled.
+    pub fn last_used(&self) -> u64 {
+        self.last_recalled.unwrap_or(0).max(self.created)
+    }
+
+    /*
+    Return a score favoring clips that are recalled often and have been
+    used lately: each recall (plus the save) counts for more the more
+    recently the clip was last used, from 16 points within the hour down
+    to 1 point after a month.
+    */
+    pub fn frecency(&self, now: u64) -> u64 {
+        let weight = match now.saturating_sub(self.last_used()) {
+            0..=3599 => 16,
+            3600..=86399 => 8,
+            86400..=604799 => 4,
+            604800..=2591999 => 2,
+            _ => 1,
+        };
+        (self.recalls + 1) * weight
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /*
+    Return the label and tags as the user would type them, like
+    "deploy query #sql #work".
+    */
+    pub fn tag_text(&self) -> String {
+        let mut words: Vec<String> = self.label.iter().cloned().collect();
+        words.extend(self.tags.iter().map(|t| format!("#{}", t)));
+        words.join(" ")
+    }
+
+    /*
+    Set the label and tags from text like that returned by `tag_text()`:
+    words starting with '#' are tags, and the rest make up the label.
+    Empty text clears both.
+    */
+    pub fn set_tag_text(&mut self, text: &str) {
+        let mut label: Vec<&str> = Vec::new();
+        self.tags.clear();
+        for word in text.split_whitespace() {
+            match word.strip_prefix('#') {
+                Some("") => {},
+                Some(tag) => if !self.has_tag(tag) {
+                    self.tags.push(tag.to_owned());
+                },
+                None => { label.push(word); },
+            }
+        }
+        self.label = if label.is_empty() {
+            None
+        } else {
+            Some(label.join(" "))
+        };
+    }
+}
+
+pub struct Index {
+    path: Utf8PathBuf,
+    clips: BTreeMap<usize, ClipMeta>,
+    // Whether `clips` has changed since it was loaded or last saved.
+    dirty: bool,
+}
+
+impl Index {
+    /*
+    Load the index from the clip directory `dir`, or start an empty one if
+    it doesn't exist or can't be read.
+    */
+    pub fn load(dir: &Utf8Path) -> Index {
+        let mut path = dir.to_path_buf();
+        path.push(INDEX_FILE);
+
+        let clips = match std::fs::read(&path) {
+            Ok(bytes) => match serde_json::from_slice(&bytes) {
+                Ok(clips) => clips,
+                Err(e) => {
+                    eprintln!("Unable to parse clip index \"{}\": {}", &path, &e);
+                    BTreeMap::new()
+                },
+            },
+            Err(_) => BTreeMap::new(),
+        };
+
+        Index { path, clips, dirty: false }
+    }
+
+    /*
+    Write the index out. It's written to a temporary file first and then
+    renamed over the old one, so it's never seen half-written.
+    */
+    pub fn save(&mut self) -> Result<(), String> {
+        let bytes = serde_json::to_vec_pretty(&self.clips)
+            .map_err(|e| format!("Error serializing clip index: {}", &e))?;
+        let tmp_path = self.path.with_extension(format!("{}.tmp", std::process::id()));
+        let mut f = std::fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(&tmp_path)
+            .map_err(|e| format!(
+                "Unable to open clip index \"{}\" for create/truncate/write: {}",
+                &tmp_path, &e
+            ))?;
+        f.write_all(&bytes)
+            .map_err(|e| format!("Error writing clip index \"{}\": {}", &tmp_path, &e))?;
+        std::fs::rename(&tmp_path, &self.path)
+            .map_err(|e| format!("Unable to replace clip index \"{}\": {}", &self.path, &e))?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    // Save the index only if it has changed.
+    pub fn save_if_changed(&mut self) -> Result<(), String> {
+        if self.dirty {
+            self.save()
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn get(&self, n: usize) -> Option<&ClipMeta> {
+        self.clips.get(&n)
+    }
+
+    pub fn get_mut(&mut self, n: usize) -> Option<&mut ClipMeta> {
+        self.dirty = true;
+        self.clips.get_mut(&n)
+    }
+
+    pub fn insert(&mut self, n: usize, meta: ClipMeta) {
+        self.dirty = true;
+        self.clips.insert(n, meta);
+    }
+
+    pub fn remove(&mut self, n: usize) -> Option<ClipMeta> {
+        let meta = self.clips.remove(&n);
+        if meta.is_some() {
+            self.dirty = true;
+        }
+        meta
+    }
+
+    /*
+    Return the metadata for clip `n`, whose file is at `path` and holds
+    `size` bytes. If the index has no entry for it, one is made, using the
+    file's modification time as its creation time.
+    */
+    pub fn reconcile(&mut self, n: usize, path: &Utf8Path, size: usize) -> &ClipMeta {
+        let mut changed = false;
+        let meta = self.clips.entry(n).or_insert_with(|| {
+            changed = true;
+            ClipMeta { created: mtime(path), size, ..Default::default() }
+        });
+        if meta.size != size {
+            meta.size = size;
+            changed = true;
+        }
+        self.dirty |= changed;
+        meta
+    }
+
+    // Return the numbers of the clips that have expired as of `now`.
+    pub fn expired(&self, now: u64) -> Vec<usize> {
+        self.clips.iter()
+            .filter(|(_, meta)| meta.is_expired(now))
+            .map(|(n, _)| *n)
+            .collect()
+    }
+
+    /*
+    Drop the entries for any clips for which `keep` returns false.
+    */
+    pub fn retain<F: FnMut(usize) -> bool>(&mut self, mut keep: F) {
+        let before = self.clips.len();
+        self.clips.retain(|n, _| keep(*n));
+        if self.clips.len() != before {
+            self.dirty = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pinned_clips_dont_expire() {
+        let dir = Utf8PathBuf::from("/nonexistent");
+        let mut index = Index::load(&dir);
+        let expiring = ClipMeta { expires: Some(100), ..Default::default() };
+        index.insert(0, expiring.clone());
+        index.insert(1, ClipMeta { pinned: true, ..expiring });
+        assert_eq!(index.expired(99), Vec::<usize>::new());
+        assert_eq!(index.expired(100), [0]);
+    }
+}
@@ -0,0 +1,91 @@
+/*!
+Advisory locking of clip directories.
+
+Anything that reads a clip directory and then changes it based on what it
+read (like picking the next clip number) holds that directory's lock, so
+two `dmxcm` processes started at the same time can't step on each other.
+The lock is an `flock()` on a hidden file in the directory.
+
+`flock()` locks belong to open files, so a thread that opened the lock
+file twice would block on itself. Locks are therefore counted per
+directory: holding a lock that the thread already holds just bumps the
+count, and the file is only closed (releasing the lock) when the last
+`Guard` for it is dropped. Each thread opens the lock file for itself, so
+threads lock each other out just as processes do.
+*/
+use std::cell::RefCell;
+use std::fs::File;
+use std::marker::PhantomData;
+use std::os::unix::io::AsRawFd;
+
+use camino::{Utf8Path, Utf8PathBuf};
+
+// Name of the lock file in each clip directory.
+const LOCK_FILE: &str = ".lock";
+
+thread_local! {
+    // The directories this thread holds locked, with their open lock files
+    // and how many `Guard`s there are for each.
+    static HELD: RefCell<Vec<(Utf8PathBuf, File, usize)>> = const { RefCell::new(Vec::new()) };
+}
+
+/*
+Holds the lock on a clip directory until dropped. It has to be dropped by
+the thread that took it, so it can't be sent to another.
+*/
+#[derive(Debug)]
+pub struct Guard {
+    dir: Utf8PathBuf,
+    not_send: PhantomData<*const ()>,
+}
+
+impl Guard {
+    fn new(dir: &Utf8Path) -> Guard {
+        Guard { dir: dir.to_path_buf(), not_send: PhantomData }
+    }
+}
+
+/*
+Lock `dir`, waiting for any other process holding it to finish.
+*/
+pub fn hold(dir: &Utf8Path) -> Result<Guard, String> {
+    HELD.with_borrow_mut(|held| hold_in(held, dir))
+}
+
+fn hold_in(held: &mut Vec<(Utf8PathBuf, File, usize)>, dir: &Utf8Path) -> Result<Guard, String> {
+    if let Some(entry) = held.iter_mut().find(|(d, _, _)| d == dir) {
+        entry.2 += 1;
+        return Ok(Guard::new(dir));
+    }
+
+    let path = dir.join(LOCK_FILE);
+    let f = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .map_err(|e| format!("Unable to open lock file \"{}\": {}", &path, &e))?;
+    // SAFETY: flock() only operates on the descriptor, which `f` keeps open.
+    if unsafe { libc::flock(f.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(format!(
+            "Unable to lock \"{}\": {}", &path, &std::io::Error::last_os_error()
+        ));
+    }
+
+    held.push((dir.to_path_buf(), f, 1));
+    Ok(Guard::new(dir))
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        HELD.with_borrow_mut(|held| {
+            if let Some(i) = held.iter().position(|(d, _, _)| *d == self.dir) {
+                held[i].2 -= 1;
+                if held[i].2 == 0 {
+                    // Closing the file releases the lock.
+                    held.swap_remove(i);
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,2525 @@
+/*!
+A clipboard manager using dmenu
+
+See `const USAGE` below for invocation.
+
+Attempts to read configuration from two files:
+
+`$XDG_CONFIG_HOME/.config/dmx.toml` for `dm_x` configuration.
+(See the `dm_x` crate for format and details.)
+
+`$XDG_CONFIG_HOME/.config/dmxcm.toml` which could contain up to the
+following options:
+
+`
+# Maximum width of lines shown in dmenu
+max_width = 120
+# How text clips are shown in the menu: "line" collapses the whole clip
+# onto one line; "summary" shows its first and last lines along with how
+# many lines and bytes it has.
+display = "line"
+# Order of clips in the menu (after pinned ones, which always come
+# first): "recency" (most recently saved or recalled first), "frequency"
+# (most often recalled first), "frecency" (a mix of the two, favoring
+# clips recalled often and lately), or "alpha" (alphabetical).
+sort = "recency"
+# Before recalling a clip, show the whole thing (one dmenu line per line
+# of the clip) and only recall it once one of those lines is chosen.
+preview = false
+# Directory to store clipboard clips (replace 1000 with your UID). This
+# is the session history, which doesn't survive a reboot.
+clips_dir = "/run/user/1000/dmxcm"
+# Directory to store pinned and kept clips, which do. The default is
+# $XDG_DATA_HOME/dmxcm (or ~/.local/share/dmxcm).
+persist_dir = "/home/you/.local/share/dmxcm"
+# Program used to access the clipboard: one of "xclip", "xsel", or
+# "wl-clipboard". The default, "auto", uses wl-clipboard if
+# WAYLAND_DISPLAY is set and xclip otherwise.
+backend = "auto"
+# Paths to the programs the backends use (the defaults are fine if
+# they're in your path).
+xclip = "xclip"
+xsel = "xsel"
+wl_copy = "wl-copy"
+wl_paste = "wl-paste"
+# Selection to save from and recall into when `--selection` isn't given:
+# one of "primary", "clipboard", or "secondary".
+selection = "clipboard"
+# Content types to save, in order of preference. The first one the
+# selection's owner offers is used; "text/plain" stands for any kind of
+# text, and is also used when none of the others is offered. Add things
+# like "text/html" or "text/uri-list" to keep those instead of text.
+targets = ["image/png", "image/jpeg", "image/gif", "image/bmp",
+           "image/webp", "text/plain"]
+# What to do when a saved value matches an existing clip: with "exact"
+# (or "whitespace", which ignores differences in whitespace), the existing
+# clip is moved to the top of the list instead of saving a duplicate;
+# "off" saves duplicates.
+dedup = "exact"
+# Limits on the clip history, applied after every save and by `--prune`;
+# the oldest clips are removed first. All are unlimited by default.
+# `max_age` is a number followed by s, m, h, d, or w.
+max_clips = 500
+max_total_bytes = 50000000
+max_age = "30d"
+# Encrypt clip files at rest: "off", "passphrase", or "keyfile". With
+# "passphrase", the passphrase is asked for with the `askpass` program
+# (or $SSH_ASKPASS); with "keyfile", the key is derived from the contents
+# of `keyfile`. Either way, the key is cached until `dmxcm --lock`. With
+# "off", encrypted clips are skipped until `dmxcm --migrate` decrypts them.
+encryption = "off"
+askpass = "/usr/bin/ssh-askpass"
+keyfile = "/home/you/.config/dmxcm.key"
+# Rules for refusing to save things that look like secrets. A text value
+# is ignored if it's shorter or longer (in characters) than the limits,
+# matches any of the regexes, or trips any of the built-in detectors:
+# "jwt" (JSON Web Tokens), "aws" (AWS keys), "pem" (PEM private keys),
+# and "entropy" (long random-looking strings). What happens then depends
+# on `ignore_action`: "drop" doesn't save it at all, and "ttl" saves it
+# to expire after `ignore_ttl`. Each decision is logged to stderr.
+ignore_min_length = 1
+ignore_max_length = 100000
+ignore_patterns = ["(?i)^password:"]
+ignore_detectors = ["jwt", "aws", "pem", "entropy"]
+ignore_action = "drop"
+ignore_ttl = "1m"
+# How long deleted clips stay in the trash (where `--undelete` can get them
+# back) before they're removed for good. "0" deletes them immediately.
+trash_retention = "7d"
+# Have every saved clip expire after this long (unless `--ttl` is given).
+# By default, clips don't expire.
+default_ttl = "1d"
+# Program used to ask for text (like tags); it's run with `-p PROMPT`.
+dmenu = "dmenu"
+# Command `--edit` opens clips with; the file to edit is added to the end.
+# dmxcm is usually run without a terminal, so this will generally need to
+# start one (and one that doesn't return until the editor exits). The
+# default is $VISUAL (or $EDITOR), run directly.
+editor = ["xterm", "-e", "vim"]
+# Whether `--edit` also puts the edited clip in the clipboard.
+edit_recall = true
+# What `--join` puts between the clips it joins: "newline", "space", or
+# "none".
+join_separator = "newline"
+# How `--type` types clips out: "xdotool" or "wtype". The default,
+# "auto", uses wtype if WAYLAND_DISPLAY is set and xdotool otherwise.
+type_backend = "auto"
+xdotool = "xdotool"
+wtype = "wtype"
+# Delay between keystrokes (in milliseconds) when typing.
+type_delay = 12
+# How often (in milliseconds) `--watch` mode checks the clipboard for
+# a new value.
+watch_interval = 500
+# Extra transformations for `--transform` to offer: filter commands that
+# are given the clip on stdin and write the result to stdout.
+[filters]
+"sort lines" = ["sort"]
+"rot13" = ["tr", "A-Za-z", "N-ZA-Mn-za-m"]
+`
+
+Any omitted options will be replaced with the defaults above (except the
+history limits, which are unset by default). If the file doesn't exist,
+all the defaults are used; if it can't be read or has a mistake in it,
+`dmxcm` reports the error and does nothing else.
+*/
+mod archive;
+mod clipboard;
+mod crypt;
+mod filter;
+mod index;
+mod lock;
+mod mime;
+mod prompt;
+mod prune;
+mod transform;
+mod trash;
+mod typeout;
+
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use dm_x::{Dmx, Item};
+
+use clipboard::{Backend, Clip, Clipboard, Programs, Selection};
+use archive::ArchivedClip;
+use crypt::Crypt;
+use index::{ClipMeta, Index};
+use prune::Policy;
+use transform::Transform;
+use typeout::Typist;
+
+const ELLIPSIS: char = '\u{2026}';
+// Stands for the elided middle of a clip in summary display mode.
+const VERTICAL_ELLIPSIS: char = '\u{22ee}';
+// Shown in place of characters that can't be displayed in the menu.
+const REPLACEMENT: char = '\u{fffd}';
+
+const USAGE: &str = "
+usage: dmxcm [ OPERATION ] [ OPTIONS ]
+
+where OPERATION is one of the following:
+
+  -s, --save      save the contents of the clipboard
+  -r, --recall    recall a saved clip into the clipboard
+  -d, --delete    delete a saved clip (into the trash)
+  -u, --undelete  restore a clip from the trash
+  -x, --expunge   delete the whole session history (kept clips stay)
+  -k, --keep      move a clip into persistent storage
+      --pin       pin a saved clip, keeping it at the top of the list
+      --unpin     unpin a pinned clip (it stays in persistent storage)
+      --lock      forget the cached encryption key
+      --migrate   encrypt saved clips (or decrypt them, with encryption off)
+  -w, --watch     run in the background, saving every new clipboard value
+  -p, --prune     remove clips beyond the configured history limits
+  -l, --list      print the saved clips, one per line
+      --get N     write the contents of clip N to stdout
+      --put       save standard input as a new clip
+      --recall-n N
+                  recall clip N into the clipboard
+  -t, --tag       set a clip's label and tags
+  -e, --edit      edit a clip, saving the result as a new clip
+      --transform transform a text clip (change case, encode, etc.) into
+                  the clipboard
+  -j, --join      pick several text clips, one after another (Escape when
+                  done), and join them into a new clip
+      --export FILE
+                  write all saved clips to the JSON archive FILE
+      --import FILE
+                  add the clips from the JSON archive FILE, skipping any
+                  that are already saved
+
+and OPTIONS may include:
+
+  --selection SEL   save from/recall into selection SEL, one of primary,
+                    clipboard, or secondary; recall also accepts both,
+                    which sets primary and clipboard at once
+  --ttl DURATION    when saving, delete the clip after DURATION (like 30s,
+                    5m, 2h, or 1d)
+  --json            with --list, print the clips as a JSON array
+  --tag TAG         only offer (or list) the clips tagged TAG
+  --preview         show the whole clip before recalling it
+  --sort MODE       order the menu by MODE, one of recency, frequency,
+                    frecency, or alpha
+  --type            recall by typing the clip into the focused window
+                    instead of setting the clipboard
+
+When tagging, enter a label and any number of #tags, like 'prod db #sql';
+the label is shown in the menu in place of the clip. Entering nothing
+removes them.
+
+Clips in persistent storage (marked with + or * in the list) are numbered
+separately; refer to them as +N.
+
+Exit status is 0 on success, 2 if there's no clip N, 3 if a value wasn't
+saved because of the ignore rules (or because its owner marked it as
+secret), and 1 for any other error.
+";
+
+// Exit statuses; see the end of `USAGE`.
+const EXIT_ERROR: i32 = 1;
+const EXIT_NO_CLIP: i32 = 2;
+const EXIT_NOT_SAVED: i32 = 3;
+
+static CFG: OnceCell<Config> = OnceCell::new();
+
+fn die(message: &str) -> ! {
+    die_with(EXIT_ERROR, message)
+}
+
+fn die_with(code: i32, message: &str) -> ! {
+    let mut stderr = std::io::stderr();
+    let _ = stderr.write_all(message.as_bytes());
+    if !message.ends_with('\n') {
+        let _ = stderr.write_all(b"\n");
+    }
+    std::process::exit(code);
+}
+
+// fn get_config_directory() -> Result<Utf8PathBuf, String> {
+//     use std::env::var;
+    
+//     match var("XDG_CONFIG_HOME") {
+//         Ok(p) => Ok(Utf8PathBuf::from(p)),
+//         Err(_) => match var("HOME") {
+//             Ok(home) => {
+//                 let mut pbuff = Utf8PathBuf::from(home);
+//                 pbuff.push(".config");
+//                 Ok(pbuff)
+//             },
+//             Err(_) => Err("Unable to determine configuration directory".to_owned())
+//         },
+//     }
+// }
+
+/*
+How saved values are compared to existing clips to detect duplicates.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dedup {
+    Off,
+    Exact,
+    Whitespace,
+}
+
+impl Dedup {
+    fn from_name(name: &str) -> Result<Dedup, String> {
+        match name {
+            "off" => Ok(Dedup::Off),
+            "exact" => Ok(Dedup::Exact),
+            "whitespace" => Ok(Dedup::Whitespace),
+            _ => Err(format!(
+                "Unknown dedup mode \"{}\"; expected one of \"off\", \"exact\", or \"whitespace\".",
+                name
+            )),
+        }
+    }
+}
+
+/*
+How text clips are rendered in the menu.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Display {
+    Line,
+    Summary,
+}
+
+impl Display {
+    fn from_name(name: &str) -> Result<Display, String> {
+        match name {
+            "line" => Ok(Display::Line),
+            "summary" => Ok(Display::Summary),
+            _ => Err(format!(
+                "Unknown display mode \"{}\"; expected \"line\" or \"summary\".",
+                name
+            )),
+        }
+    }
+}
+
+/*
+The order clips are listed in.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortMode {
+    Recency,
+    Frequency,
+    Frecency,
+    Alpha,
+}
+
+impl SortMode {
+    fn from_name(name: &str) -> Result<SortMode, String> {
+        match name {
+            "recency" => Ok(SortMode::Recency),
+            "frequency" => Ok(SortMode::Frequency),
+            "frecency" => Ok(SortMode::Frecency),
+            "alpha" => Ok(SortMode::Alpha),
+            _ => Err(format!(
+                "Unknown sort mode \"{}\"; expected one of \"recency\", \"frequency\", \"frecency\", or \"alpha\".",
+                name
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ConfigFile {
+    pub max_width: Option<usize>,
+    pub display: Option<String>,
+    pub preview: Option<bool>,
+    pub sort: Option<String>,
+    pub clips_dir: Option<String>,
+    pub persist_dir: Option<String>,
+    pub backend: Option<String>,
+    pub xclip: Option<String>,
+    pub xsel: Option<String>,
+    pub wl_copy: Option<String>,
+    pub wl_paste: Option<String>,
+    pub selection: Option<String>,
+    pub targets: Option<Vec<String>>,
+    pub dedup: Option<String>,
+    pub max_clips: Option<usize>,
+    pub max_total_bytes: Option<u64>,
+    pub max_age: Option<String>,
+    pub encryption: Option<String>,
+    pub askpass: Option<String>,
+    pub keyfile: Option<String>,
+    pub ignore_min_length: Option<usize>,
+    pub ignore_max_length: Option<usize>,
+    pub ignore_patterns: Option<Vec<String>>,
+    pub ignore_detectors: Option<Vec<String>>,
+    pub ignore_action: Option<String>,
+    pub ignore_ttl: Option<String>,
+    pub default_ttl: Option<String>,
+    pub trash_retention: Option<String>,
+    pub dmenu: Option<String>,
+    pub editor: Option<Vec<String>>,
+    pub edit_recall: Option<bool>,
+    pub join_separator: Option<String>,
+    pub type_backend: Option<String>,
+    pub xdotool: Option<String>,
+    pub wtype: Option<String>,
+    pub type_delay: Option<u64>,
+    pub watch_interval: Option<u64>,
+    pub filters: Option<BTreeMap<String, Vec<String>>>,
+}
+
+#[derive(Debug)]
+struct Config {
+    max_width: usize,
+    display: Display,
+    preview: bool,
+    sort: SortMode,
+    clips_dir: Utf8PathBuf,
+    persist_dir: Utf8PathBuf,
+    backend: Backend,
+    programs: Programs,
+    selection: Selection,
+    targets: Vec<String>,
+    dedup: Dedup,
+    limits: Policy,
+    crypt: Crypt,
+    // Where the encryption key gets cached.
+    runtime_dir: Utf8PathBuf,
+    ignore: filter::Rules,
+    // In seconds.
+    default_ttl: Option<u64>,
+    // How long trashed clips are kept, in seconds.
+    trash_retention: u64,
+    // Run directly for free-text input.
+    dmenu: Utf8PathBuf,
+    // Empty if not configured, meaning use $VISUAL or $EDITOR.
+    editor: Vec<String>,
+    edit_recall: bool,
+    join_separator: String,
+    typist: Typist,
+    watch_interval: u64,
+    // User-defined `--transform` filters, by name.
+    filters: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        // SAFETY: getuid() takes no arguments and always succeeds.
+        let uid = unsafe { libc::getuid() }.to_string();
+        let runtime_dir: Utf8PathBuf = match std::env::var("XDG_RUNTIME_DIR") {
+            Ok(dir) => Utf8PathBuf::from(dir),
+            Err(_) => ["/", "run", "user", &uid].iter().collect(),
+        };
+        let clips_dir: Utf8PathBuf = ["/", "run", "user", &uid, "dmxcm"]
+            .iter().collect();
+        let mut persist_dir = dmxtools::data_directory()
+            .unwrap_or_else(|e| die(e));
+        persist_dir.push("dmxcm");
+        let crypt = Crypt::new(crypt::Mode::Off, None, &persist_dir, &runtime_dir);
+        
+        Config {
+            max_width: 120,
+            display: Display::Line,
+            preview: false,
+            sort: SortMode::Recency,
+            clips_dir,
+            persist_dir,
+            backend: Backend::Auto,
+            programs: Programs {
+                xclip: "xclip".into(),
+                xsel: "xsel".into(),
+                wl_copy: "wl-copy".into(),
+                wl_paste: "wl-paste".into(),
+            },
+            selection: Selection::Clipboard,
+            targets: mime::DEFAULT_PREFERENCES.iter()
+                .map(|t| t.to_string())
+                .collect(),
+            dedup: Dedup::Exact,
+            limits: Policy::default(),
+            crypt,
+            runtime_dir,
+            ignore: filter::Rules::default(),
+            default_ttl: None,
+            trash_retention: 7 * 86400,
+            dmenu: "dmenu".into(),
+            editor: Vec::new(),
+            edit_recall: true,
+            join_separator: "\n".to_owned(),
+            typist: Typist::default(),
+            watch_interval: 500,
+            filters: BTreeMap::new(),
+        }
+    }
+}
+
+fn configure_dmxcm() -> Result<Config, String> {
+    let mut config_path = dmxtools::config_directory()?;
+    config_path.push("dmxcm.toml");
+    
+    let bytes = match std::fs::read(&config_path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Config::default());
+        },
+        Err(e) => {
+            return Err(format!(
+                "Unable to read dmxcm configuration file {}: {}.",
+                &config_path, &e
+            ));
+        },
+    };
+    
+    let usr_cfg: ConfigFile = toml::from_slice(&bytes)
+        .map_err(|e| format!(
+            "Error deserializing dmxcm configuration file {}: {}.",
+            &config_path, &e
+        ))?;
+    
+    let mut cfg = Config::default();
+    if let Some(width) = usr_cfg.max_width {
+        cfg.max_width = width;
+    }
+    if let Some(name) = usr_cfg.display {
+        cfg.display = Display::from_name(&name)?;
+    }
+    if let Some(preview) = usr_cfg.preview {
+        cfg.preview = preview;
+    }
+    if let Some(name) = usr_cfg.sort {
+        cfg.sort = SortMode::from_name(&name)?;
+    }
+    if let Some(dir) = usr_cfg.clips_dir {
+        cfg.clips_dir = Utf8PathBuf::from(dir);
+    }
+    if let Some(dir) = usr_cfg.persist_dir {
+        cfg.persist_dir = Utf8PathBuf::from(dir);
+    }
+    if let Some(name) = usr_cfg.backend {
+        cfg.backend = Backend::from_name(&name)?;
+    }
+    if let Some(path) = usr_cfg.xclip {
+        cfg.programs.xclip = Utf8PathBuf::from(path);
+    }
+    if let Some(path) = usr_cfg.xsel {
+        cfg.programs.xsel = Utf8PathBuf::from(path);
+    }
+    if let Some(path) = usr_cfg.wl_copy {
+        cfg.programs.wl_copy = Utf8PathBuf::from(path);
+    }
+    if let Some(path) = usr_cfg.wl_paste {
+        cfg.programs.wl_paste = Utf8PathBuf::from(path);
+    }
+    if let Some(name) = usr_cfg.selection {
+        cfg.selection = Selection::from_name(&name)?;
+    }
+    if let Some(targets) = usr_cfg.targets {
+        cfg.targets = targets;
+    }
+    if let Some(name) = usr_cfg.dedup {
+        cfg.dedup = Dedup::from_name(&name)?;
+    }
+    cfg.limits.max_clips = usr_cfg.max_clips;
+    cfg.limits.max_total_bytes = usr_cfg.max_total_bytes;
+    if let Some(age) = usr_cfg.max_age {
+        cfg.limits.max_age = Some(parse_duration(&age)?);
+    }
+    let keyfile = usr_cfg.keyfile.map(|path| crypt::Mode::Keyfile(Utf8PathBuf::from(path)));
+    let mode = match usr_cfg.encryption.as_deref() {
+        None | Some("off") => crypt::Mode::Off,
+        Some("passphrase") => crypt::Mode::Passphrase,
+        Some("keyfile") => match keyfile.clone() {
+            Some(mode) => mode,
+            None => {
+                return Err("encryption = \"keyfile\" requires the keyfile option.".to_owned());
+            },
+        },
+        Some(x) => {
+            return Err(format!(
+                "Unknown encryption mode \"{}\"; expected one of \"off\", \"passphrase\", or \"keyfile\".",
+                x
+            ));
+        },
+    };
+    let askpass = usr_cfg.askpass.map(Utf8PathBuf::from);
+    cfg.crypt = if mode == crypt::Mode::Off {
+        // `--migrate` may still need the key to decrypt clips saved while
+        // encryption was on.
+        Crypt::disabled(
+            keyfile.unwrap_or(crypt::Mode::Passphrase),
+            askpass,
+            &cfg.persist_dir,
+            &cfg.runtime_dir
+        )
+    } else {
+        Crypt::new(mode, askpass, &cfg.persist_dir, &cfg.runtime_dir)
+    };
+    cfg.ignore.min_length = usr_cfg.ignore_min_length;
+    cfg.ignore.max_length = usr_cfg.ignore_max_length;
+    for pat in usr_cfg.ignore_patterns.unwrap_or_default().iter() {
+        let re = regex::Regex::new(pat)
+            .map_err(|e| format!("Invalid ignore_patterns regex \"{}\": {}", pat, &e))?;
+        cfg.ignore.patterns.push(re);
+    }
+    for name in usr_cfg.ignore_detectors.unwrap_or_default().iter() {
+        cfg.ignore.detectors.push(filter::Detector::from_name(name)?);
+    }
+    cfg.ignore.action = match usr_cfg.ignore_action.as_deref() {
+        None | Some("drop") => filter::Action::Drop,
+        Some("ttl") => {
+            let ttl = usr_cfg.ignore_ttl.as_deref().unwrap_or("1m");
+            filter::Action::Expire(parse_duration(ttl)?)
+        },
+        Some(x) => {
+            return Err(format!(
+                "Unknown ignore_action \"{}\"; expected \"drop\" or \"ttl\".", x
+            ));
+        },
+    };
+    if let Some(ttl) = usr_cfg.default_ttl {
+        cfg.default_ttl = Some(parse_duration(&ttl)?);
+    }
+    if let Some(retention) = usr_cfg.trash_retention {
+        cfg.trash_retention = parse_duration(&retention)?;
+    }
+    if let Some(path) = usr_cfg.dmenu {
+        cfg.dmenu = Utf8PathBuf::from(path);
+    }
+    if let Some(editor) = usr_cfg.editor {
+        cfg.editor = editor;
+    }
+    if let Some(recall) = usr_cfg.edit_recall {
+        cfg.edit_recall = recall;
+    }
+    if let Some(name) = usr_cfg.join_separator {
+        cfg.join_separator = match name.as_str() {
+            "newline" => "\n",
+            "space" => " ",
+            "none" => "",
+            x => {
+                return Err(format!(
+                    "Unknown join_separator \"{}\"; expected one of \"newline\", \"space\", or \"none\".",
+                    x
+                ));
+            },
+        }.to_owned();
+    }
+    if let Some(name) = usr_cfg.type_backend {
+        cfg.typist.backend = typeout::Backend::from_name(&name)?;
+    }
+    if let Some(path) = usr_cfg.xdotool {
+        cfg.typist.xdotool = Utf8PathBuf::from(path);
+    }
+    if let Some(path) = usr_cfg.wtype {
+        cfg.typist.wtype = Utf8PathBuf::from(path);
+    }
+    if let Some(ms) = usr_cfg.type_delay {
+        cfg.typist.delay = ms;
+    }
+    if let Some(ms) = usr_cfg.watch_interval {
+        cfg.watch_interval = ms;
+    }
+    if let Some(filters) = usr_cfg.filters {
+        cfg.filters = filters;
+    }
+    
+    Ok(cfg)
+}
+
+/*
+Return a copy of `text` with surrounding whitespace stripped and all
+sequences of interior whitespace collapsed down to a single space.
+
+Limit the length to `max_len` characters, and set the final character
+to an ellipsis if it would exceed that length.
+*/
+fn collapse_whitespace(text: &str, max_len: usize) -> String {
+    let mut out_chars: Vec<char> = Vec::with_capacity(max_len.min(text.len()));
+    let mut last_char_was_ws: bool = true;
+    let mut chars = text.trim().chars().peekable();
+
+    while out_chars.len() < max_len {
+        let c = match chars.next() {
+            Some(c) => c,
+            None => { break; },
+        };
+        if c.is_whitespace() {
+            if !last_char_was_ws {
+                out_chars.push(' ');
+                last_char_was_ws = true;
+            }
+        } else {
+            out_chars.push(c);
+            last_char_was_ws = false;
+        }
+    }
+    
+    if chars.peek().is_some() {
+        let _ = out_chars.pop();
+        out_chars.push(ELLIPSIS);
+    }
+    
+    let output: String = out_chars.into_iter().collect();
+    output
+}
+
+/*
+Return `text` with anything that could break or disguise a menu line
+replaced by something visible: C0 control characters (like NUL, or the
+ESC that starts ANSI escape sequences) by their Unicode control pictures
+(like ␀ and ␛), DEL by ␡, and C1 controls and the invisible bidirectional
+formatting characters (which can make text display in a misleading order)
+by �. This is only ever applied to what's displayed, never to the clips.
+*/
+fn sanitize_for_menu(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\u{0}'..='\u{1f}' => char::from_u32(0x2400 + c as u32).unwrap_or(REPLACEMENT),
+            '\u{7f}' => '\u{2421}',
+            '\u{80}'..='\u{9f}'
+            | '\u{61c}'
+            | '\u{200e}' | '\u{200f}'
+            | '\u{202a}'..='\u{202e}'
+            | '\u{2066}'..='\u{2069}' => REPLACEMENT,
+            c => c,
+        })
+        .collect()
+}
+
+/*
+Return a one-line summary of multi-line `text`: its first and last
+nonblank lines (each collapsed to about half of `max_len` characters),
+followed by its line and byte counts, like
+"fn main() { ⋮ }  [12 lines, 340B]". Text with only one nonblank line is
+just collapsed.
+*/
+fn summarize(text: &str, max_len: usize) -> String {
+    let nonblank: Vec<&str> = text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    let counts = format!("[{} lines, {}]", text.lines().count(), mime::human_size(text.len()));
+    
+    match nonblank.as_slice() {
+        [] => counts,
+        [_] => collapse_whitespace(text, max_len),
+        [first, .., last] => {
+            let half = max_len.saturating_sub(counts.len() + 5) / 2;
+            format!(
+                "{} {} {}  {}",
+                &collapse_whitespace(first, half.max(1)),
+                VERTICAL_ELLIPSIS,
+                &collapse_whitespace(last, half.max(1)),
+                &counts
+            )
+        },
+    }
+}
+
+/*
+Return the first `max_len` characters of `line`, ending with an ellipsis
+if anything had to be cut off. Unlike `collapse_whitespace()`, this leaves
+indentation alone.
+*/
+fn truncate(line: &str, max_len: usize) -> String {
+    if line.chars().count() <= max_len {
+        return line.to_owned();
+    }
+    let mut out: String = line.chars().take(max_len.saturating_sub(1)).collect();
+    out.push(ELLIPSIS);
+    out
+}
+
+/*
+Return a short rendering of how long ago `then` was (relative to `now`,
+both in seconds since the Unix epoch), like "5m" or "3d".
+*/
+fn relative_age(then: u64, now: u64) -> String {
+    let secs = now.saturating_sub(then);
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        86400..=31535999 => format!("{}d", secs / 86400),
+        _ => format!("{}y", secs / 31536000),
+    }
+}
+
+/*
+Parse a duration like "90s", "5m", "2h", "7d", or "1w" into seconds. A bare
+number is taken as seconds.
+*/
+fn parse_duration(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let (num, mult) = match text.char_indices().last() {
+        Some((i, 's')) => (&text[..i], 1),
+        Some((i, 'm')) => (&text[..i], 60),
+        Some((i, 'h')) => (&text[..i], 3600),
+        Some((i, 'd')) => (&text[..i], 86400),
+        Some((i, 'w')) => (&text[..i], 604800),
+        _ => (text, 1),
+    };
+    let n: u64 = num.trim().parse()
+        .map_err(|_| format!("Can't understand \"{}\" as a duration.", text))?;
+    Ok(n * mult)
+}
+
+/*
+An `Entry` represents a single saved clipboard item, and holds a path
+to the file as well as the file's contents.
+*/
+struct Entry {
+    path: Utf8PathBuf,
+    // This makes them easily sortable.
+    n: usize,
+    contents: Vec<u8>,
+    // What the index knows about this clip.
+    meta: ClipMeta,
+    // Whether this clip is in persistent storage.
+    kept: bool,
+}
+
+impl Entry {
+    // Instantiate an `Entry` from the path of a file in the clip directory.
+    fn from_path(path: &Utf8Path) -> Result<Entry, String> {
+        let n: usize = path.file_name()
+            .ok_or(format!("Path \"{}\" has no filename.", &path))?
+            .parse()
+            .map_err(|e| format!("Path \"{}\" filename can't be parsed as usize: {}", &path, &e))?;
+        
+        let contents = std::fs::read(path)
+            .map_err(|e| format!("Unable to read \"{}\": {}", &path, &e))?;
+        let contents = CFG.get().unwrap().crypt.decrypt(contents)
+            .map_err(|e| format!("Unable to read \"{}\": {}", &path, &e))?;
+        
+        let ent = Entry {
+            path: path.to_path_buf(),
+            n,
+            contents,
+            meta: ClipMeta::default(),
+            kept: false,
+        };
+        
+        Ok(ent)
+    }
+    
+    // Return the contents as a `Clip` of its saved type.
+    fn clip(&self) -> Clip {
+        Clip { mime: self.meta.mime.clone(), bytes: self.contents.clone() }
+    }
+    
+    // Return how to refer to this clip on the command line: "N" or "+N".
+    fn id(&self) -> String {
+        if self.kept {
+            format!("+{}", self.n)
+        } else {
+            self.n.to_string()
+        }
+    }
+    
+    /*
+    Return a one-line rendering of the contents, at most `max_len`
+    characters long for text.
+    */
+    fn preview(&self, max_len: usize) -> String {
+        let preview = if let Some(label) = &self.meta.label {
+            collapse_whitespace(label, max_len)
+        } else if mime::is_text(&self.meta.mime) {
+            let text = String::from_utf8_lossy(&self.contents);
+            match CFG.get().unwrap().display {
+                Display::Line => collapse_whitespace(&text, max_len),
+                Display::Summary => summarize(&text, max_len),
+            }
+        } else {
+            mime::describe(&self.meta.mime, &self.contents)
+        };
+        sanitize_for_menu(&preview)
+    }
+}
+
+impl Item for Entry {
+    fn key_len(&self) -> usize {
+        self.n.to_string().chars().count()
+    }
+    
+    fn line(&self, key_len: usize) -> Vec<u8> {
+        let collapsed = self.preview(CFG.get().unwrap().max_width);
+        let pin_char = if self.meta.pinned {
+            '*'
+        } else if self.kept {
+            '+'
+        } else {
+            ' '
+        };
+        let mut linestr = format!(
+            "{:0>width$} {} {:>3}  {}",
+            &self.n,
+            pin_char,
+            &relative_age(self.meta.created, index::now()),
+            &collapsed,
+            width = key_len
+        );
+        for tag in self.meta.tags.iter() {
+            linestr.push_str(" #");
+            linestr.push_str(&sanitize_for_menu(tag));
+        }
+        linestr.into_bytes()
+    }
+}
+
+/*
+A single line of a clip, as shown in the recall preview.
+*/
+struct PreviewLine(String);
+
+impl Item for PreviewLine {
+    fn key_len(&self) -> usize { 0 }
+    
+    fn line(&self, _key_len: usize) -> Vec<u8> {
+        self.0.clone().into_bytes()
+    }
+}
+
+/*
+Show the whole of `ent` in the menu, one line per line of the clip, and
+return whether the user chose to go ahead (by picking any of them).
+*/
+fn confirm_recall(dmx: &Dmx, ent: &Entry) -> Result<bool, String> {
+    let max_len = CFG.get().unwrap().max_width;
+    let lines: Vec<PreviewLine> = if mime::is_text(&ent.meta.mime) {
+        String::from_utf8_lossy(&ent.contents).lines()
+            .map(|line| line.replace('\t', "    "))
+            .map(|line| PreviewLine(truncate(&sanitize_for_menu(&line), max_len)))
+            .collect()
+    } else {
+        // The type comes from whatever owned the selection, so it can't be
+        // trusted any more than the contents.
+        vec![PreviewLine(sanitize_for_menu(&mime::describe(&ent.meta.mime, &ent.contents)))]
+    };
+    Ok(dmx.select("recall?", &lines)?.is_some())
+}
+
+/*
+How `--list --json` describes each clip.
+*/
+#[derive(Serialize)]
+struct Listing<'a> {
+    id: String,
+    n: usize,
+    kept: bool,
+    pinned: bool,
+    mime: &'a str,
+    size: usize,
+    created: u64,
+    // Seconds since the clip was saved.
+    age: u64,
+    label: Option<&'a str>,
+    tags: &'a [String],
+    preview: String,
+}
+
+impl<'a> Listing<'a> {
+    fn new(ent: &'a Entry, now: u64) -> Listing<'a> {
+        Listing {
+            id: ent.id(),
+            n: ent.n,
+            kept: ent.kept,
+            pinned: ent.meta.pinned,
+            mime: &ent.meta.mime,
+            size: ent.contents.len(),
+            created: ent.meta.created,
+            age: now.saturating_sub(ent.meta.created),
+            label: ent.meta.label.as_deref(),
+            tags: &ent.meta.tags,
+            preview: ent.preview(CFG.get().unwrap().max_width),
+        }
+    }
+}
+
+/*
+Print `entries` to stdout, either as they'd appear in the menu or as JSON.
+*/
+fn list_entries(entries: &[Entry], json: bool) -> Result<(), String> {
+    let mut stdout = std::io::stdout().lock();
+    if json {
+        let now = index::now();
+        let listings: Vec<Listing> = entries.iter()
+            .map(|ent| Listing::new(ent, now))
+            .collect();
+        serde_json::to_writer_pretty(&mut stdout, &listings)
+            .map_err(|e| format!("Error writing clip list: {}", &e))?;
+        writeln!(stdout).map_err(|e| format!("Error writing clip list: {}", &e))?;
+    } else {
+        let key_len = entries.iter().map(|ent| ent.key_len()).max().unwrap_or(0);
+        for ent in entries.iter() {
+            let mut line = ent.line(key_len);
+            line.push(b'\n');
+            stdout.write_all(&line)
+                .map_err(|e| format!("Error writing clip list: {}", &e))?;
+        }
+    }
+    Ok(())
+}
+
+/*
+Return the entry referred to on the command line as `id`: "N" for clip N
+in the session history, or "+N" for clip N in persistent storage.
+*/
+fn find_entry<'a>(entries: &'a [Entry], id: &str) -> Result<Option<&'a Entry>, String> {
+    let (kept, num) = match id.strip_prefix('+') {
+        Some(num) => (true, num),
+        None => (false, id),
+    };
+    let n: usize = num.parse()
+        .map_err(|_| format!("\"{}\" isn't a clip number.\n", id))?;
+    Ok(entries.iter().find(|ent| ent.n == n && ent.kept == kept))
+}
+
+/*
+Return a Vec of `Entry`s representing all the saved clips in the clip
+directory, with the metadata recorded for them in `index`.
+
+Hidden files (like the index itself) are skipped, and expired clips are
+deleted. The index is brought up to date with the clips actually present
+and saved if that changed it.
+*/
+fn read_entries(dir: &Utf8Path, index: &mut Index) -> Result<Vec<Entry>, String> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let now = index::now();
+    
+    for p in dir.read_dir_utf8()
+        .map_err(|e| format!("Unable to read directory \"{}\": {}", &dir, &e))?
+        .flatten()
+    {
+        if p.file_name().starts_with('.') {
+            continue;
+        }
+        match Entry::from_path(p.path()) {
+            Ok(mut e) => {
+                e.meta = index.reconcile(e.n, &e.path, e.contents.len()).clone();
+                if !e.meta.is_expired(now) {
+                    entries.push(e);
+                } else if let Err(e) = remove_entry(&e, index) {
+                    eprintln!("{}", &e);
+                }
+            },
+            Err(e) => {
+                // Without the key, none of the encrypted clips can be
+                // read, so there's no point going on.
+                if let Some(e) = CFG.get().unwrap().crypt.key_error() {
+                    return Err(format!("Unable to read clips: {}", e));
+                }
+                eprintln!("{}", &e);
+            },
+        }
+    }
+    
+    // Clips that are there but couldn't be read (say, for want of the key)
+    // keep their metadata.
+    index.retain(|n| dir.join(n.to_string()).exists());
+    index.save_if_changed()?;
+    
+    Ok(entries)
+}
+
+/*
+The two tiers of clip storage: the session history in `clips_dir`, which
+lives in volatile storage, and the clips in `persist_dir` that have been
+pinned or explicitly kept, which survive reboots.
+
+Both directories are locked while a `Tiers` is loaded. Operations that
+wait on the user should `unlock()` first, so other `dmxcm` processes
+aren't held up, and `lock()` again before changing anything.
+*/
+struct Tiers {
+    session: Index,
+    kept: Index,
+    locks: Vec<lock::Guard>,
+}
+
+impl Tiers {
+    fn load() -> Result<Tiers, String> {
+        let cfg = CFG.get().unwrap();
+        let locks = vec![lock::hold(&cfg.clips_dir)?, lock::hold(&cfg.persist_dir)?];
+        Ok(Tiers {
+            session: Index::load(&cfg.clips_dir),
+            kept: Index::load(&cfg.persist_dir),
+            locks,
+        })
+    }
+    
+    /*
+    Lock both directories (if they aren't already) and reload their
+    indices, which other processes may have changed in the meantime.
+    */
+    fn lock(&mut self) -> Result<(), String> {
+        if !self.locks.is_empty() {
+            return Ok(());
+        }
+        let cfg = CFG.get().unwrap();
+        self.locks.push(lock::hold(&cfg.clips_dir)?);
+        self.locks.push(lock::hold(&cfg.persist_dir)?);
+        self.session = Index::load(&cfg.clips_dir);
+        self.kept = Index::load(&cfg.persist_dir);
+        Ok(())
+    }
+    
+    /*
+    Return whether `ent` is still the clip it was when it was read. Clip
+    numbers get reused, so while the directories were unlocked it may
+    have been deleted and another clip saved under its number.
+    */
+    fn is_current(&self, ent: &Entry) -> bool {
+        let index = if ent.kept { &self.kept } else { &self.session };
+        matches!(
+            index.get(ent.n),
+            Some(meta) if meta.created == ent.meta.created && meta.size == ent.meta.size
+        )
+    }
+    
+    /*
+    Lock both directories again in order to change `ent`, making sure it's
+    still current.
+    */
+    fn lock_for(&mut self, ent: &Entry) -> Result<(), String> {
+        self.lock()?;
+        if self.is_current(ent) {
+            Ok(())
+        } else {
+            Err(format!("Clip {} was changed by another dmxcm in the meantime.", ent.id()))
+        }
+    }
+    
+    // Save any changes and release the locks.
+    fn unlock(&mut self) {
+        self.save();
+        self.locks.clear();
+    }
+    
+    /*
+    Return the clips from both tiers.
+    */
+    fn read_entries(&mut self) -> Result<Vec<Entry>, String> {
+        let mut entries = read_entries(&CFG.get().unwrap().clips_dir, &mut self.session)?;
+        let mut kept = read_entries(&CFG.get().unwrap().persist_dir, &mut self.kept)?;
+        for ent in kept.iter_mut() {
+            ent.kept = true;
+        }
+        entries.append(&mut kept);
+        Ok(entries)
+    }
+    
+    // Return the index of the tier `ent` is in.
+    fn index_for(&mut self, ent: &Entry) -> &mut Index {
+        if ent.kept {
+            &mut self.kept
+        } else {
+            &mut self.session
+        }
+    }
+    
+    fn save(&mut self) {
+        for index in [&mut self.session, &mut self.kept] {
+            index.save_if_changed().unwrap_or_else(|e| eprintln!("{}", &e));
+        }
+    }
+    
+    /*
+    Move `ent` from the session history into persistent storage (if it
+    isn't there already), returning its metadata in its new home. Kept
+    clips don't expire.
+    */
+    fn keep(&mut self, ent: &Entry) -> Result<&mut ClipMeta, String> {
+        let n = if ent.kept {
+            ent.n
+        } else {
+            let dir = CFG.get().unwrap().persist_dir.as_path();
+            let n = next_n(dir, &read_entries(dir, &mut self.kept)?);
+            write_clip_file_n(dir, n, &ent.contents)?;
+            self.kept.insert(n, ent.meta.clone());
+            remove_entry(ent, &mut self.session)?;
+            n
+        };
+        let meta = self.kept.get_mut(n)
+            .ok_or_else(|| format!("Kept clip {} missing from the index.", n))?;
+        meta.expires = None;
+        Ok(meta)
+    }
+}
+
+/*
+Sort `entries` for display: pinned clips first, then in the order given
+by `mode`, with ties going to the newest clip.
+*/
+fn sort_for_menu(entries: &mut [Entry], mode: SortMode) {
+    let now = index::now();
+    let max_len = CFG.get().unwrap().max_width;
+    // Previews are costly to make, so each entry's key is only made once.
+    entries.sort_by_cached_key(|ent| {
+        let (score, text) = match mode {
+            SortMode::Recency => (ent.meta.last_used(), String::new()),
+            SortMode::Frequency => (ent.meta.recalls, String::new()),
+            SortMode::Frecency => (ent.meta.frecency(now), String::new()),
+            SortMode::Alpha => (0, ent.preview(max_len).to_lowercase()),
+        };
+        (Reverse(ent.meta.pinned), Reverse(score), text, Reverse(ent.meta.created), Reverse(ent.n))
+    });
+}
+
+/*
+Return the number the next saved clip in `dir` should get: one more than
+the highest-numbered clip in `entries`, skipping over any files in `dir`
+that couldn't be read as clips (like encrypted ones, with encryption off).
+*/
+fn next_n(dir: &Utf8Path, entries: &[Entry]) -> usize {
+    let mut n = match entries.iter().map(|ent| ent.n).max() {
+        Some(n) => n + 1,
+        None => 0,
+    };
+    while dir.join(n.to_string()).exists() {
+        n += 1;
+    }
+    n
+}
+
+/*
+Return the part of a clip of type `mime` holding `bytes` that's compared
+when looking for duplicates under the given `mode`.
+*/
+fn dedup_key<'a>(mime: &str, bytes: &'a [u8], mode: Dedup) -> Cow<'a, [u8]> {
+    if mode == Dedup::Whitespace && mime::is_text(mime) {
+        let text = collapse_whitespace(&String::from_utf8_lossy(bytes), usize::MAX);
+        Cow::Owned(text.into_bytes())
+    } else {
+        Cow::Borrowed(bytes)
+    }
+}
+
+/*
+Return the saved `Entry` (if any) that `clip` duplicates under `mode`.
+*/
+fn find_duplicate<'a>(entries: &'a [Entry], clip: &Clip, mode: Dedup) -> Option<&'a Entry> {
+    if mode == Dedup::Off {
+        return None;
+    }
+    
+    let key = dedup_key(&clip.mime, &clip.bytes, mode);
+    entries.iter().find(|ent| {
+        ent.meta.mime == clip.mime && dedup_key(&ent.meta.mime, &ent.contents, mode) == key
+    })
+}
+
+/*
+Save the contents of the given selection to the clip directory, returning
+where it went (if anywhere).
+*/
+fn save_clipboard(
+    cb: &dyn Clipboard,
+    sel: Selection,
+    dir: &Utf8Path,
+    ttl: Option<u64>
+) -> Result<Option<Saved>, String> {
+    match clipboard::read_preferred(cb, sel, &CFG.get().unwrap().targets)? {
+        Some(clip) => save_clip(dir, sel, &clip, ttl),
+        None => {
+            eprintln!("dmxcm: not saving clip: marked secret by its owner");
+            Ok(None)
+        },
+    }
+}
+
+/*
+Check a text `clip` against the ignore rules. Returns `None` if it
+shouldn't be saved at all, or else the time (if any) it should expire.
+*/
+fn screen_clip(clip: &Clip) -> Option<Option<u64>> {
+    if !mime::is_text(&clip.mime) {
+        return Some(None);
+    }
+    let rules = &CFG.get().unwrap().ignore;
+    let reason = match rules.check(&String::from_utf8_lossy(&clip.bytes)) {
+        Some(reason) => reason,
+        None => { return Some(None); },
+    };
+    
+    match rules.action {
+        filter::Action::Drop => {
+            eprintln!("dmxcm: not saving clip: {}", &reason);
+            None
+        },
+        filter::Action::Expire(ttl) => {
+            eprintln!("dmxcm: saving clip to expire in {}s: {}", ttl, &reason);
+            Some(Some(index::now() + ttl))
+        },
+    }
+}
+
+/*
+Where `save_clip()` put a value.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Saved {
+    // In the session history, as the clip with this number.
+    Session(usize),
+    // Nowhere; it's already in persistent storage as this clip.
+    Kept(usize),
+}
+
+// Written the way clips are referred to on the command line.
+impl std::fmt::Display for Saved {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Saved::Session(n) => write!(f, "{}", n),
+            Saved::Kept(n) => write!(f, "+{}", n),
+        }
+    }
+}
+
+/*
+Save `clip` as a new, highest-numbered clip, recording in the index its
+content type and that it came from selection `sel`. Returns where it
+went, or `None` if the ignore rules said not to save it.
+
+The clip expires after `ttl` seconds, if given (or sooner, if the ignore
+rules say so).
+
+If `clip` duplicates an existing clip, that one is replaced instead: it
+gets renumbered to the top, keeping its label and recall history. If it
+duplicates a clip in persistent storage, nothing is saved at all.
+*/
+fn save_clip(
+    dir: &Utf8Path,
+    sel: Selection,
+    clip: &Clip,
+    ttl: Option<u64>
+) -> Result<Option<Saved>, String> {
+    let cfg = CFG.get().unwrap();
+    let _lock = lock::hold(dir)?;
+    let _kept_lock = lock::hold(&cfg.persist_dir)?;
+    let expires = match screen_clip(clip) {
+        Some(expires) => expires,
+        None => { return Ok(None); },
+    };
+    let expires = match (expires, ttl.map(|t| index::now() + t)) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    
+    let kept = read_entries(&cfg.persist_dir, &mut Index::load(&cfg.persist_dir))?;
+    if let Some(ent) = find_duplicate(&kept, clip, cfg.dedup) {
+        return Ok(Some(Saved::Kept(ent.n)));
+    }
+    
+    let mut index = Index::load(dir);
+    let entries = read_entries(dir, &mut index)?;
+    let n = next_n(dir, &entries);
+    let mut meta = ClipMeta::new(sel, &clip.mime, clip.bytes.len());
+    meta.expires = expires;
+    
+    write_clip_file_n(dir, n, &clip.bytes)?;
+    if let Some(old) = find_duplicate(&entries, clip, cfg.dedup) {
+        meta.label = old.meta.label.clone();
+        meta.tags = old.meta.tags.clone();
+        meta.recalls = old.meta.recalls;
+        meta.last_recalled = old.meta.last_recalled;
+        meta.pinned = old.meta.pinned;
+        remove_entry(old, &mut index)?;
+    }
+    index.insert(n, meta);
+    index.save()?;
+    
+    if let Err(e) = prune_clips(dir) {
+        eprintln!("{}", &e);
+    }
+    Ok(Some(Saved::Session(n)))
+}
+
+/*
+Delete the clips in `dir` whose expiry times have passed. This only needs
+the index, so it works even when the clips are encrypted and the key isn't
+available.
+*/
+fn purge_expired(dir: &Utf8Path) -> Result<(), String> {
+    let _lock = lock::hold(dir)?;
+    let mut index = Index::load(dir);
+    for n in index.expired(index::now()) {
+        let path = dir.join(n.to_string());
+        match std::fs::remove_file(&path) {
+            Ok(()) => {},
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {},
+            Err(e) => {
+                eprintln!("Error removing expired clip {}: {}", &path, &e);
+                continue;
+            },
+        }
+        index.remove(n);
+    }
+    index.save_if_changed()
+}
+
+/*
+Remove the clips that exceed the configured history limits, returning
+the numbers of those removed and why.
+*/
+fn prune_clips(dir: &Utf8Path) -> Result<Vec<(usize, prune::Reason)>, String> {
+    let policy = &CFG.get().unwrap().limits;
+    if policy.is_unlimited() {
+        return Ok(Vec::new());
+    }
+    
+    let _lock = lock::hold(dir)?;
+    let mut index = Index::load(dir);
+    let entries = read_entries(dir, &mut index)?;
+    let clips: Vec<(usize, &ClipMeta)> = entries.iter()
+        .map(|ent| (ent.n, &ent.meta))
+        .collect();
+    let victims = policy.victims(&clips, index::now());
+    
+    for (n, _) in victims.iter() {
+        if let Some(ent) = entries.iter().find(|ent| ent.n == *n) {
+            remove_entry(ent, &mut index)?;
+        }
+    }
+    index.save_if_changed()?;
+    Ok(victims)
+}
+
+/*
+Delete the file of the given `Entry`, along with its index entry.
+*/
+fn remove_entry(ent: &Entry, index: &mut Index) -> Result<(), String> {
+    std::fs::remove_file(&ent.path)
+        .map_err(|e| format!("Error removing clipboard file {}: {}", &ent.path, &e))?;
+    index.remove(ent.n);
+    Ok(())
+}
+
+/*
+Move the given `Entry` into its directory's trash, removing it from the
+index. If the trash is turned off, it's just deleted.
+*/
+fn trash_entry(ent: &Entry, index: &mut Index) -> Result<(), String> {
+    if CFG.get().unwrap().trash_retention == 0 {
+        return remove_entry(ent, index);
+    }
+    let dir = ent.path.parent()
+        .ok_or_else(|| format!("Path \"{}\" has no parent.", &ent.path))?;
+    trash::put(dir, ent.n, &ent.path, &ent.meta, index::now())?;
+    index.remove(ent.n);
+    Ok(())
+}
+
+/*
+Permanently delete the clips that have been in the trash of `dir` longer
+than the configured retention time.
+*/
+fn purge_trash(dir: &Utf8Path) -> Result<(), String> {
+    let _lock = lock::hold(dir)?;
+    trash::purge(dir, CFG.get().unwrap().trash_retention, index::now())?;
+    Ok(())
+}
+
+/*
+Return the clips in the trash of both tiers (but not ones that have
+expired since), along with `Entry`s for showing them in the menu, most
+recently deleted first.
+*/
+fn read_trash() -> Result<Vec<(trash::Trashed, Entry)>, String> {
+    let cfg = CFG.get().unwrap();
+    let now = index::now();
+    let mut items: Vec<(trash::Trashed, Entry)> = Vec::new();
+    for (dir, kept) in [(&cfg.clips_dir, false), (&cfg.persist_dir, true)] {
+        for t in trash::list(dir)?.into_iter().filter(|t| !t.meta.is_expired(now)) {
+            let contents = std::fs::read(&t.path)
+                .map_err(|e| format!("Unable to read \"{}\": {}", &t.path, &e))
+                .and_then(|bytes| cfg.crypt.decrypt(bytes));
+            match contents {
+                Ok(contents) => {
+                    let ent = Entry {
+                        path: t.path.clone(),
+                        n: t.n,
+                        contents,
+                        meta: t.meta.clone(),
+                        kept,
+                    };
+                    items.push((t, ent));
+                },
+                Err(e) => { eprintln!("{}", &e); },
+            }
+        }
+    }
+    items.sort_by_key(|(t, _)| std::cmp::Reverse(t.deleted));
+    Ok(items)
+}
+
+/*
+Put the trashed clip `t` back into the tier it was deleted from, as the
+newest clip there.
+*/
+fn undelete(tiers: &mut Tiers, t: &trash::Trashed, kept: bool) -> Result<usize, String> {
+    let cfg = CFG.get().unwrap();
+    tiers.lock()?;
+    let (dir, index) = if kept {
+        (&cfg.persist_dir, &mut tiers.kept)
+    } else {
+        (&cfg.clips_dir, &mut tiers.session)
+    };
+    let n = next_n(dir, &read_entries(dir, index)?);
+    t.restore(&dir.join(n.to_string()))?;
+    index.insert(n, t.meta.clone());
+    tiers.save();
+    Ok(n)
+}
+
+/*
+Write `bytes` to a file in the clip directory with the given number,
+encrypting it if encryption is enabled.
+
+The file is written under a temporary (hidden) name and then renamed into
+place, so nothing ever sees a partly-written clip.
+*/
+fn write_clip_file_n(dir: &Utf8Path, n: usize, bytes: &[u8]) -> Result<(), String> {
+    write_clip_file(&dir.join(n.to_string()), bytes)
+}
+
+/*
+Like `write_clip_file_n()`, but for a clip file at any `path`.
+*/
+fn write_clip_file(path: &Utf8Path, bytes: &[u8]) -> Result<(), String> {
+    let bytes = CFG.get().unwrap().crypt.encrypt(bytes)?;
+    let name = path.file_name()
+        .ok_or_else(|| format!("Path \"{}\" has no filename.", path))?;
+    let tmp_path = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+    let mut f = std::fs::OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(&tmp_path)
+        .map_err(|e| {
+            format!(
+                "Unable to open \"{}\" for create/truncate/write: {}",
+                &tmp_path, &e
+            )
+        })?;
+
+    f.write_all(&bytes)
+        .map_err(|e| format!("Error writing to \"{}\": {}", &tmp_path, &e))?;
+    std::fs::rename(&tmp_path, path)
+        .map_err(|e| {
+            let _ = std::fs::remove_file(&tmp_path);
+            format!("Unable to move \"{}\" into place as \"{}\": {}", &tmp_path, path, &e)
+        })
+}
+
+/*
+Rewrite the clips in `dir`, and those in its trash, to match the
+configuration: plaintext ones are encrypted if encryption is on, and
+encrypted ones are decrypted if it's off. Returns how many were
+converted.
+*/
+fn migrate_clips(dir: &Utf8Path) -> Result<usize, String> {
+    let crypt = &CFG.get().unwrap().crypt;
+    let mut paths: Vec<Utf8PathBuf> = dir.read_dir_utf8()
+        .map_err(|e| format!("Unable to read directory \"{}\": {}", &dir, &e))?
+        .flatten()
+        .filter(|p| p.file_name().parse::<usize>().is_ok())
+        .map(|p| p.path().to_path_buf())
+        .collect();
+    paths.extend(trash::list(dir)?.into_iter().map(|t| t.path));
+    
+    let mut count: usize = 0;
+    for path in paths.iter() {
+        let raw = std::fs::read(path)
+            .map_err(|e| format!("Unable to read \"{}\": {}", path, &e))?;
+        if crypt::is_encrypted(&raw) == crypt.enabled() {
+            continue;
+        }
+        let contents = crypt.decrypt_anyway(raw)
+            .map_err(|e| format!("Unable to read \"{}\": {}", path, &e))?;
+        write_clip_file(path, &contents)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/*
+Saves each new value it's shown to the clip directory.
+
+The clipboard programs we drive can't subscribe to selection ownership
+changes, so `--watch` mode polls the clipboard and hands every value it
+reads to `Watcher::observe()`, which saves it only if it differs from the
+last value seen. Each read is one call to `Watcher::poll()`, so a scripted
+sequence of values can be fed through it one at a time.
+*/
+struct Watcher {
+    dir: Utf8PathBuf,
+    selection: Selection,
+    // Lifetime of saved clips, in seconds.
+    ttl: Option<u64>,
+    last: Option<Clip>,
+}
+
+impl Watcher {
+    /*
+    Start watching selection `sel`, treating the most recently saved clip
+    (if any) as the last value seen, so restarting the watcher doesn't
+    re-save it. Saved clips expire after `ttl` seconds, if given.
+    */
+    fn new(dir: &Utf8Path, sel: Selection, ttl: Option<u64>, entries: &[Entry]) -> Watcher {
+        let last = entries.iter()
+            .max_by_key(|ent| ent.n)
+            .map(|ent| ent.clip());
+        
+        Watcher { dir: dir.to_path_buf(), selection: sel, ttl, last }
+    }
+    
+    /*
+    Save `value` as a new clip if it's nonempty and different from the
+    last value observed. Returns where it went, if it was saved.
+    */
+    fn observe(&mut self, value: Clip) -> Result<Option<Saved>, String> {
+        if value.bytes.is_empty() || self.last.as_ref() == Some(&value) {
+            return Ok(None);
+        }
+        
+        let saved = save_clip(&self.dir, self.selection, &value, self.ttl)?;
+        self.last = Some(value);
+        Ok(saved)
+    }
+    
+    /*
+    Read a value with `read` and observe it, returning where it went, if
+    it was saved. Values marked secret (`None`) are skipped.
+    Errors are reported but not returned; the clipboard is frequently
+    unowned or briefly unreadable, and that shouldn't stop the watcher.
+    */
+    fn poll<F>(&mut self, read: &mut F) -> Option<Saved>
+    where F: FnMut() -> Result<Option<Clip>, String>
+    {
+        match read().and_then(|value| match value {
+            Some(value) => self.observe(value),
+            None => Ok(None),
+        }) {
+            Ok(saved) => saved,
+            Err(e) => {
+                eprintln!("{}", &e);
+                None
+            },
+        }
+    }
+    
+    // Poll `read` every `interval` forever.
+    fn run<F>(&mut self, interval: std::time::Duration, mut read: F) -> !
+    where F: FnMut() -> Result<Option<Clip>, String>
+    {
+        loop {
+            self.poll(&mut read);
+            std::thread::sleep(interval);
+        }
+    }
+}
+
+/*
+Return the command to edit a file with: the configured `editor`, or else
+$VISUAL, $EDITOR, or vi.
+*/
+fn editor_command() -> Vec<String> {
+    let cfg = CFG.get().unwrap();
+    if !cfg.editor.is_empty() {
+        return cfg.editor.clone();
+    }
+    let editor = ["VISUAL", "EDITOR"].iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|val| !val.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_owned());
+    editor.split_whitespace().map(|s| s.to_owned()).collect()
+}
+
+/*
+Open the contents of `ent` in the user's editor and return the edited
+text, or `None` if it wasn't changed. The text is written to a private
+temporary file in the runtime directory, which is removed afterward.
+*/
+fn edit_entry(ent: &Entry) -> Result<Option<Vec<u8>>, String> {
+    if !mime::is_text(&ent.meta.mime) {
+        return Err(format!("Can't edit clip {}; it isn't text ({}).\n", ent.n, &ent.meta.mime));
+    }
+    let cmd = editor_command();
+    let (prog, args) = cmd.split_first()
+        .ok_or_else(|| "The editor option is empty.\n".to_owned())?;
+    
+    let path = CFG.get().unwrap().runtime_dir
+        .join(format!("dmxcm-edit-{}.txt", std::process::id()));
+    crypt::write_private(&path, &ent.contents)?;
+    
+    let result = std::process::Command::new(prog)
+        .args(args)
+        .arg(&path)
+        .status()
+        .map_err(|e| format!("Error running editor \"{}\": {}\n", prog, &e))
+        .and_then(|status| if status.success() {
+            std::fs::read(&path)
+                .map_err(|e| format!("Unable to read edited clip \"{}\": {}\n", &path, &e))
+        } else {
+            Err(format!("Editor \"{}\" exited with {}; not saving.\n", prog, &status))
+        });
+    if let Err(e) = std::fs::remove_file(&path) {
+        eprintln!("Error removing \"{}\": {}", &path, &e);
+    }
+    
+    let bytes = result?;
+    if bytes == ent.contents || bytes.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(bytes))
+    }
+}
+
+/*
+Write the clips in `entries` to the archive at `path`, returning how many
+were written.
+*/
+fn export_entries(entries: &[Entry], path: &Utf8Path) -> Result<usize, String> {
+    let clips: Vec<ArchivedClip> = entries.iter()
+        .map(|ent| ArchivedClip::new(
+            ent.n, ent.kept, index::mtime(&ent.path), &ent.meta, &ent.contents
+        ))
+        .collect();
+    archive::write(path, &clips)?;
+    Ok(clips.len())
+}
+
+/*
+Add the clips from the archive at `path` to the history, returning how
+many were added and how many were skipped (for being expired or already
+saved). Clips keep their metadata but are renumbered to follow the ones
+already saved, oldest first; ones that were kept or pinned go back into
+persistent storage.
+*/
+fn import_archive(tiers: &mut Tiers, path: &Utf8Path) -> Result<(usize, usize), String> {
+    let cfg = CFG.get().unwrap();
+    tiers.lock()?;
+    let mut clips = archive::read(path)?;
+    clips.sort_by_key(|ac| (ac.meta.created, ac.n));
+    // Identical content is never imported twice, even with dedup turned off.
+    let mode = match cfg.dedup {
+        Dedup::Whitespace => Dedup::Whitespace,
+        _ => Dedup::Exact,
+    };
+    let mut session = read_entries(&cfg.clips_dir, &mut tiers.session)?;
+    let mut kept = read_entries(&cfg.persist_dir, &mut tiers.kept)?;
+    let now = index::now();
+    let (mut imported, mut skipped) = (0, 0);
+    
+    for ac in clips.iter() {
+        let clip = Clip { mime: ac.meta.mime.clone(), bytes: ac.contents()? };
+        if ac.meta.is_expired(now)
+            || find_duplicate(&session, &clip, mode).is_some()
+            || find_duplicate(&kept, &clip, mode).is_some()
+        {
+            skipped += 1;
+            continue;
+        }
+        
+        let to_kept = ac.kept || ac.meta.pinned;
+        let (dir, index, entries) = if to_kept {
+            (&cfg.persist_dir, &mut tiers.kept, &mut kept)
+        } else {
+            (&cfg.clips_dir, &mut tiers.session, &mut session)
+        };
+        let n = next_n(dir, entries);
+        write_clip_file_n(dir, n, &clip.bytes)?;
+        let path = dir.join(n.to_string());
+        if ac.modified > 0 {
+            let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(ac.modified);
+            if let Err(e) = std::fs::File::options().write(true).open(&path)
+                .and_then(|f| f.set_modified(mtime))
+            {
+                eprintln!("Unable to set modification time of \"{}\": {}", &path, &e);
+            }
+        }
+        
+        let mut meta = ac.meta.clone();
+        meta.size = clip.bytes.len();
+        index.insert(n, meta.clone());
+        entries.push(Entry { path, n, contents: clip.bytes, meta, kept: to_kept });
+        imported += 1;
+    }
+    
+    tiers.save();
+    Ok((imported, skipped))
+}
+
+/*
+Have the user pick entries from `entries` one at a time until they
+cancel, showing the ones picked so far in the prompt. Returns the picked
+entries in the order they were chosen.
+*/
+fn pick_several(dmx: &Dmx, mut entries: Vec<Entry>) -> Result<Vec<Entry>, String> {
+    let mut chosen: Vec<Entry> = Vec::new();
+    while !entries.is_empty() {
+        let prompt = if chosen.is_empty() {
+            "join ▶".to_owned()
+        } else {
+            let ids: Vec<String> = chosen.iter().map(|ent| ent.id()).collect();
+            format!("join {} ▶", ids.join(" + "))
+        };
+        match dmx.select(&prompt, &entries)? {
+            Some(n) => { chosen.push(entries.remove(n)); },
+            None => { break; },
+        }
+    }
+    Ok(chosen)
+}
+
+/*
+Return the transformations `--transform` offers: the built-in ones,
+followed by the user's filters.
+*/
+fn transforms() -> Vec<Transform> {
+    let mut v: Vec<Transform> = transform::Builtin::ALL.iter()
+        .map(|b| Transform::Builtin(*b))
+        .collect();
+    for (name, command) in CFG.get().unwrap().filters.iter() {
+        v.push(Transform::Filter { name: name.clone(), command: command.clone() });
+    }
+    v
+}
+
+/*
+Insert the contents of the given `Entry` into each of the selections in
+`sels`, under the content type it was saved as.
+*/
+fn pipe_entry_to_clipboard(
+    cb: &dyn Clipboard,
+    sels: &[Selection],
+    ent: &Entry
+) -> Result<(), String> {
+    let clip = ent.clip();
+    for sel in sels.iter() {
+        clipboard::write_clip(cb, *sel, &clip)?;
+    }
+    Ok(())
+}
+
+/*
+Recall `ent`: type it out if `--type` was given, otherwise put it in the
+selection(s) chosen by `opts`.
+*/
+fn recall_entry(cb: &dyn Clipboard, opts: &Opts, ent: &Entry) -> Result<(), String> {
+    if opts.type_out {
+        if !mime::is_text(&ent.meta.mime) {
+            return Err(format!("Can't type clip {}; it isn't text ({}).\n", ent.n, &ent.meta.mime));
+        }
+        CFG.get().unwrap().typist.type_text(&ent.contents)
+    } else {
+        pipe_entry_to_clipboard(cb, &opts.recall_selections()?, ent)
+    }
+}
+
+/*
+Note that `ent` has just been recalled.
+*/
+fn record_recall(tiers: &mut Tiers, ent: &Entry) -> Result<(), String> {
+    tiers.lock_for(ent)?;
+    if let Some(meta) = tiers.index_for(ent).get_mut(ent.n) {
+        meta.record_recall();
+    }
+    tiers.save();
+    Ok(())
+}
+
+/*
+The operation and options given on the command line.
+*/
+struct Opts {
+    op: String,
+    // Value of the `--selection` option, if given.
+    selection: Option<String>,
+    // Value of the `--ttl` option, in seconds.
+    ttl: Option<u64>,
+    // Whether `--json` was given.
+    json: bool,
+    // The clip number given to `--get` or `--recall-n`.
+    clip: Option<String>,
+    // The file given to `--export` or `--import`.
+    file: Option<Utf8PathBuf>,
+    // Value of the `--tag` option, if given.
+    tag: Option<String>,
+    // Whether `--preview` was given.
+    preview: bool,
+    // Whether `--type` was given.
+    type_out: bool,
+    // Value of the `--sort` option, if given.
+    sort: Option<SortMode>,
+}
+
+/*
+Return the next argument from `args` as the value of option `opt`.
+*/
+fn option_value<I: Iterator<Item = String>>(opt: &str, args: &mut I) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("{} option requires a value.\n", opt))
+}
+
+impl Opts {
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Opts, String> {
+        let op = args.next().ok_or_else(|| USAGE.to_owned())?;
+        let mut opts = Opts {
+            op,
+            selection: None,
+            ttl: None,
+            json: false,
+            clip: None,
+            file: None,
+            tag: None,
+            preview: false,
+            type_out: false,
+            sort: None,
+        };
+        match opts.op.as_str() {
+            "--get" | "--recall-n" => {
+                opts.clip = Some(option_value(&opts.op, &mut args)?);
+            },
+            "--export" | "--import" => {
+                opts.file = Some(option_value(&opts.op, &mut args)?.into());
+            },
+            "-s" | "--save" | "-r" | "--recall" | "-d" | "--delete"
+            | "-u" | "--undelete" | "-x" | "--expunge" | "-k" | "--keep"
+            | "--pin" | "--unpin" | "--lock" | "--migrate" | "-w" | "--watch"
+            | "-p" | "--prune" | "-l" | "--list" | "--put" | "-t" | "--tag"
+            | "-e" | "--edit" | "--transform" | "-j" | "--join"
+            | "-h" | "--help" => {},
+            _ => {
+                return Err(format!("Unknown operation: {}\n{}", &opts.op, USAGE));
+            },
+        }
+        
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--selection" => {
+                    opts.selection = Some(option_value(&arg, &mut args)?);
+                },
+                "--ttl" => {
+                    opts.ttl = Some(parse_duration(&option_value(&arg, &mut args)?)?);
+                },
+                "--json" => { opts.json = true; },
+                "--preview" => { opts.preview = true; },
+                "--type" => { opts.type_out = true; },
+                "--sort" => {
+                    opts.sort = Some(SortMode::from_name(&option_value(&arg, &mut args)?)?);
+                },
+                "--tag" => {
+                    let tag = option_value(&arg, &mut args)?;
+                    opts.tag = Some(tag.trim_start_matches('#').to_owned());
+                },
+                _ => {
+                    return Err(format!("Unknown option: {}\n{}", &arg, USAGE));
+                },
+            }
+        }
+        
+        Ok(opts)
+    }
+    
+    // The single selection to save from.
+    fn save_selection(&self) -> Result<Selection, String> {
+        match &self.selection {
+            Some(name) => Selection::from_name(name),
+            None => Ok(CFG.get().unwrap().selection),
+        }
+    }
+    
+    // Lifetime of saved clips: the `--ttl` value or the configured default.
+    fn ttl(&self) -> Option<u64> {
+        self.ttl.or(CFG.get().unwrap().default_ttl)
+    }
+    
+    // The selection(s) to recall into.
+    fn recall_selections(&self) -> Result<Vec<Selection>, String> {
+        match self.selection.as_deref() {
+            Some("both") => Ok(vec![Selection::Primary, Selection::Clipboard]),
+            _ => Ok(vec![self.save_selection()?]),
+        }
+    }
+    
+    /*
+    Return the entry named by the `--get`/`--recall-n` argument, or exit
+    if there isn't one.
+    */
+    fn clip_entry<'a>(&self, entries: &'a [Entry]) -> &'a Entry {
+        let id = self.clip.as_deref().unwrap_or_default();
+        match find_entry(entries, id) {
+            Ok(Some(ent)) => ent,
+            Ok(None) => die_with(EXIT_NO_CLIP, &format!("No clip {}.\n", id)),
+            Err(e) => die(&e),
+        }
+    }
+}
+
+fn main() {
+    let opts = Opts::parse(std::env::args().skip(1))
+        .unwrap_or_else(|e| die(&format!("{}\n", e.trim_end())));
+    if opts.op == "-h" || opts.op == "--help" {
+        print!("{}", USAGE);
+        return;
+    }
+
+    // The configuration says whether to encrypt and what not to save, so
+    // carrying on with the defaults after a mistake in it isn't safe.
+    let cfg = configure_dmxcm().unwrap_or_else(|e| die(&format!("{}\n", &e)));
+    CFG.set(cfg).unwrap();
+    
+    for dir in [&CFG.get().unwrap().clips_dir, &CFG.get().unwrap().persist_dir] {
+        std::fs::create_dir_all(dir)
+            .unwrap_or_else(|e| die(&format!("Unable to create clip directory \"{}\": {}", dir, &e)));
+    }
+    
+    for dir in [&CFG.get().unwrap().clips_dir, &CFG.get().unwrap().persist_dir] {
+        if let Err(e) = purge_expired(dir) {
+            eprintln!("{}", &e);
+        }
+        if let Err(e) = purge_trash(dir) {
+            eprintln!("{}", &e);
+        }
+    }
+    
+    // This shouldn't need the key in order to forget it.
+    if opts.op == "--lock" {
+        match CFG.get().unwrap().crypt.lock() {
+            Ok(true) => { println!("encryption key forgotten"); },
+            Ok(false) => { println!("no cached encryption key"); },
+            Err(e) => { die(&e); },
+        }
+        return;
+    }
+    
+    let cb = clipboard::open(CFG.get().unwrap().backend, &CFG.get().unwrap().programs);
+    
+    let mut tiers = Tiers::load().unwrap_or_else(|e| die(&e));
+    let mut entries = tiers.read_entries().unwrap_or_else(|e| die(&e));
+    // Anything that goes on to change the clip directories locks them
+    // again first.
+    tiers.unlock();
+    if let Some(tag) = &opts.tag {
+        entries.retain(|ent| ent.meta.has_tag(tag));
+    }
+    sort_for_menu(&mut entries, opts.sort.unwrap_or(CFG.get().unwrap().sort));
+    
+    match opts.op.as_str() {
+        
+        "-r" | "--recall" => {
+            let dmx = Dmx::automagiconf();            
+            
+            if let Some(n) = dmx.select("▶", &entries).unwrap_or_else(|e| die(&e)) {
+                let ent = &entries[n];
+                let preview = opts.preview || CFG.get().unwrap().preview;
+                if preview && !confirm_recall(&dmx, ent).unwrap_or_else(|e| die(&e)) {
+                    return;
+                }
+                recall_entry(cb.as_ref(), &opts, ent).unwrap_or_else(|e| die(&e));
+                if let Err(e) = record_recall(&mut tiers, ent) {
+                    eprintln!("{}", &e);
+                }
+            }
+        },
+        
+        "--recall-n" => {
+            let ent = opts.clip_entry(&entries);
+            recall_entry(cb.as_ref(), &opts, ent).unwrap_or_else(|e| die(&e));
+            if let Err(e) = record_recall(&mut tiers, ent) {
+                eprintln!("{}", &e);
+            }
+        },
+        
+        "-l" | "--list" => {
+            list_entries(&entries, opts.json).unwrap_or_else(|e| die(&e));
+        },
+        
+        "--get" => {
+            let ent = opts.clip_entry(&entries);
+            std::io::stdout().write_all(&ent.contents)
+                .unwrap_or_else(|e| die(&format!("Error writing clip: {}\n", &e)));
+        },
+        
+        "--put" => {
+            let sel = opts.save_selection().unwrap_or_else(|e| die(&e));
+            let mut bytes: Vec<u8> = Vec::new();
+            std::io::stdin().read_to_end(&mut bytes)
+                .unwrap_or_else(|e| die(&format!("Error reading standard input: {}\n", &e)));
+            if bytes.is_empty() {
+                die_with(EXIT_NOT_SAVED, "Nothing to save.\n");
+            }
+            let clip = Clip { mime: mime::TEXT_PLAIN.to_owned(), bytes };
+            match save_clip(&CFG.get().unwrap().clips_dir, sel, &clip, opts.ttl()) {
+                Ok(Some(saved)) => { println!("{}", saved); },
+                Ok(None) => { std::process::exit(EXIT_NOT_SAVED); },
+                Err(e) => { die(&e); },
+            }
+        },
+        
+        "-s" | "--save" => {
+            let sel = opts.save_selection().unwrap_or_else(|e| die(&e));
+            match save_clipboard(cb.as_ref(), sel, &CFG.get().unwrap().clips_dir, opts.ttl()) {
+                Ok(Some(_)) => {},
+                Ok(None) => { std::process::exit(EXIT_NOT_SAVED); },
+                Err(e) => { die(&e); },
+            }
+        },
+        
+        "-w" | "--watch" => {
+            let sel = opts.save_selection().unwrap_or_else(|e| die(&e));
+            let cfg = CFG.get().unwrap();
+            let interval = std::time::Duration::from_millis(cfg.watch_interval);
+            entries.retain(|ent| !ent.kept);
+            let mut watcher = Watcher::new(&cfg.clips_dir, sel, opts.ttl(), &entries);
+            watcher.run(interval, || clipboard::read_preferred(cb.as_ref(), sel, &cfg.targets));
+        },
+        
+        "-p" | "--prune" => {
+            let victims = prune_clips(&CFG.get().unwrap().clips_dir)
+                .unwrap_or_else(|e| die(&e));
+            for (n, reason) in victims.iter() {
+                println!("removed clip {} ({})", n, reason);
+            }
+            println!("{} clip(s) removed", victims.len());
+        },
+        
+        "-d" | "--delete" => {
+            let dmx = Dmx::automagiconf();
+            
+            if let Some(n) = dmx.select("⏏", &entries).unwrap_or_else(|e| die(&e)) {
+                let ent = &entries[n];
+                tiers.lock_for(ent).unwrap_or_else(|e| die(&e));
+                if let Err(e) = trash_entry(ent, tiers.index_for(ent)) {
+                    eprintln!("{}", &e);
+                }
+                tiers.save();
+            }
+        },
+        
+        "-u" | "--undelete" => {
+            let dmx = Dmx::automagiconf();
+            let (trashed, trashed_entries): (Vec<_>, Vec<_>) = read_trash()
+                .unwrap_or_else(|e| die(&e))
+                .into_iter()
+                .unzip();
+            
+            if let Some(n) = dmx.select("♻", &trashed_entries).unwrap_or_else(|e| die(&e)) {
+                undelete(&mut tiers, &trashed[n], trashed_entries[n].kept)
+                    .unwrap_or_else(|e| die(&e));
+            }
+        },
+        
+        "-k" | "--keep" => {
+            let dmx = Dmx::automagiconf();
+            entries.retain(|ent| !ent.kept);
+            
+            if let Some(n) = dmx.select("+", &entries).unwrap_or_else(|e| die(&e)) {
+                tiers.lock_for(&entries[n]).unwrap_or_else(|e| die(&e));
+                if let Err(e) = tiers.keep(&entries[n]) {
+                    eprintln!("{}", &e);
+                }
+                tiers.save();
+            }
+        },
+        
+        "--pin" | "--unpin" => {
+            let pin = opts.op == "--pin";
+            let dmx = Dmx::automagiconf();
+            entries.retain(|ent| ent.meta.pinned != pin);
+            
+            if let Some(n) = dmx.select("📌", &entries).unwrap_or_else(|e| die(&e)) {
+                tiers.lock_for(&entries[n]).unwrap_or_else(|e| die(&e));
+                match tiers.keep(&entries[n]) {
+                    Ok(meta) => { meta.pinned = pin; },
+                    Err(e) => { eprintln!("{}", &e); },
+                }
+                tiers.save();
+            }
+        },
+        
+        "-t" | "--tag" => {
+            let dmx = Dmx::automagiconf();
+            
+            if let Some(n) = dmx.select("#", &entries).unwrap_or_else(|e| die(&e)) {
+                let ent = &entries[n];
+                let current = ent.meta.tag_text();
+                let suggestions: Vec<String> = if current.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![current]
+                };
+                let text = prompt::text(&CFG.get().unwrap().dmenu, "label #tags:", &suggestions)
+                    .unwrap_or_else(|e| die(&e));
+                if let Some(text) = text {
+                    tiers.lock_for(ent).unwrap_or_else(|e| die(&e));
+                    if let Some(meta) = tiers.index_for(ent).get_mut(ent.n) {
+                        meta.set_tag_text(&text);
+                    }
+                    tiers.save();
+                }
+            }
+        },
+        
+        "-e" | "--edit" => {
+            let sels = opts.recall_selections().unwrap_or_else(|e| die(&e));
+            let dmx = Dmx::automagiconf();
+            
+            if let Some(n) = dmx.select("✎", &entries).unwrap_or_else(|e| die(&e)) {
+                let ent = &entries[n];
+                let bytes = match edit_entry(ent).unwrap_or_else(|e| die(&e)) {
+                    Some(bytes) => bytes,
+                    None => { return; },
+                };
+                let clip = Clip { mime: ent.meta.mime.clone(), bytes };
+                let dir = &CFG.get().unwrap().clips_dir;
+                save_clip(dir, ent.meta.selection, &clip, opts.ttl())
+                    .unwrap_or_else(|e| die(&e));
+                if CFG.get().unwrap().edit_recall {
+                    for sel in sels.iter() {
+                        clipboard::write_clip(cb.as_ref(), *sel, &clip)
+                            .unwrap_or_else(|e| die(&e));
+                    }
+                }
+            }
+        },
+        
+        "--transform" => {
+            let sels = opts.recall_selections().unwrap_or_else(|e| die(&e));
+            let dmx = Dmx::automagiconf();
+            entries.retain(|ent| mime::is_text(&ent.meta.mime));
+            
+            let ent = match dmx.select("▶", &entries).unwrap_or_else(|e| die(&e)) {
+                Some(n) => &entries[n],
+                None => { return; },
+            };
+            let transforms = transforms();
+            if let Some(n) = dmx.select("ƒ", &transforms).unwrap_or_else(|e| die(&e)) {
+                let text = String::from_utf8_lossy(&ent.contents);
+                let result = transforms[n].apply(&text)
+                    .unwrap_or_else(|e| die(&format!("{}\n", &e)));
+                let clip = Clip { mime: mime::TEXT_PLAIN.to_owned(), bytes: result.into_bytes() };
+                for sel in sels.iter() {
+                    clipboard::write_clip(cb.as_ref(), *sel, &clip)
+                        .unwrap_or_else(|e| die(&e));
+                }
+            }
+        },
+        
+        "-j" | "--join" => {
+            let sel = opts.save_selection().unwrap_or_else(|e| die(&e));
+            let sels = opts.recall_selections().unwrap_or_else(|e| die(&e));
+            let dmx = Dmx::automagiconf();
+            entries.retain(|ent| mime::is_text(&ent.meta.mime));
+            
+            let chosen = pick_several(&dmx, entries).unwrap_or_else(|e| die(&e));
+            if chosen.is_empty() {
+                return;
+            }
+            let separator = CFG.get().unwrap().join_separator.as_bytes();
+            let bytes = chosen.iter()
+                .map(|ent| ent.contents.as_slice())
+                .collect::<Vec<&[u8]>>()
+                .join(separator);
+            let clip = Clip { mime: mime::TEXT_PLAIN.to_owned(), bytes };
+            save_clip(&CFG.get().unwrap().clips_dir, sel, &clip, opts.ttl())
+                .unwrap_or_else(|e| die(&e));
+            for sel in sels.iter() {
+                clipboard::write_clip(cb.as_ref(), *sel, &clip)
+                    .unwrap_or_else(|e| die(&e));
+            }
+        },
+        
+        "--export" => {
+            let path = opts.file.as_ref().unwrap();
+            entries.sort_unstable_by_key(|ent| (ent.kept, ent.n));
+            let count = export_entries(&entries, path).unwrap_or_else(|e| die(&e));
+            println!("{} clip(s) exported to {}", count, path);
+        },
+        
+        "--import" => {
+            let path = opts.file.as_ref().unwrap();
+            let (imported, skipped) = import_archive(&mut tiers, path)
+                .unwrap_or_else(|e| die(&e));
+            if let Err(e) = prune_clips(&CFG.get().unwrap().clips_dir) {
+                eprintln!("{}", &e);
+            }
+            println!("{} clip(s) imported, {} skipped", imported, skipped);
+        },
+        
+        "--migrate" => {
+            let cfg = CFG.get().unwrap();
+            tiers.lock().unwrap_or_else(|e| die(&e));
+            let mut count: usize = 0;
+            for dir in [&cfg.clips_dir, &cfg.persist_dir] {
+                count += migrate_clips(dir).unwrap_or_else(|e| die(&e));
+            }
+            if cfg.crypt.enabled() {
+                println!("{} clip(s) encrypted", count);
+            } else {
+                println!("{} clip(s) decrypted", count);
+            }
+        },
+        
+        "-x" | "--expunge" => {
+            tiers.lock().unwrap_or_else(|e| die(&e));
+            entries.retain(|ent| tiers.is_current(ent));
+            for ent in entries.iter().filter(|ent| !ent.kept && !ent.meta.pinned) {
+                if let Err(e) = trash_entry(ent, &mut tiers.session) {
+                    eprintln!("{}", &e)
+                }
+            }
+            tiers.save();
+        }
+        
+        op => unreachable!("operation {} passed Opts::parse()", op),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clipboard::mock::Mock;
+    
+    // How many `TestDir`s there are; the last one out cleans up.
+    static TEST_DIRS: std::sync::Mutex<usize> = std::sync::Mutex::new(0);
+    
+    // Where the tests keep everything.
+    fn test_base() -> Utf8PathBuf {
+        Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("dmxcm-test-{}", std::process::id()))
+    }
+    
+    /*
+    A directory for a test to keep its clips in, removed when dropped.
+    When the last one goes, so does everything else the tests made.
+    */
+    struct TestDir(Utf8PathBuf);
+    
+    impl std::ops::Deref for TestDir {
+        type Target = Utf8Path;
+        
+        fn deref(&self) -> &Utf8Path {
+            &self.0
+        }
+    }
+    
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let mut count = TEST_DIRS.lock().unwrap_or_else(|e| e.into_inner());
+            let _ = std::fs::remove_dir_all(&self.0);
+            *count -= 1;
+            if *count == 0 {
+                let _ = std::fs::remove_dir_all(test_base());
+            }
+        }
+    }
+    
+    /*
+    Set up the configuration the tests share (the defaults, with the clip
+    directories somewhere harmless), and return a new, empty directory
+    for the test `name` to keep its clips in.
+    */
+    fn test_dir(name: &str) -> TestDir {
+        let base = test_base();
+        CFG.get_or_init(|| {
+            let mut cfg = Config::default();
+            cfg.clips_dir = base.join("session");
+            cfg.persist_dir = base.join("kept");
+            cfg.runtime_dir = base.clone();
+            cfg.crypt = Crypt::new(crypt::Mode::Off, None, &cfg.persist_dir, &cfg.runtime_dir);
+            cfg
+        });
+        let mut count = TEST_DIRS.lock().unwrap_or_else(|e| e.into_inner());
+        std::fs::create_dir_all(base.join("kept")).unwrap();
+        let dir = base.join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        *count += 1;
+        TestDir(dir)
+    }
+    
+    // Return the (number, contents) of every clip in `dir`, in order.
+    fn saved_clips(dir: &Utf8Path) -> Vec<(usize, String)> {
+        let mut entries = read_entries(dir, &mut Index::load(dir)).unwrap();
+        entries.sort_by_key(|ent| ent.n);
+        entries.iter()
+            .map(|ent| (ent.n, String::from_utf8_lossy(&ent.contents).into_owned()))
+            .collect()
+    }
+    
+    #[test]
+    fn watcher_saves_each_new_value() {
+        let dir = test_dir("watcher");
+        let cb = Mock::default();
+        let targets = &CFG.get().unwrap().targets;
+        let mut read = || clipboard::read_preferred(&cb, Selection::Clipboard, targets);
+        let mut watcher = Watcher::new(&dir, Selection::Clipboard, None, &[]);
+        
+        let script = ["one", "one", "", "two", "", "two", "one", "one"];
+        let saved: Vec<Option<usize>> = script.iter()
+            .map(|text| {
+                cb.set_text(text);
+                match watcher.poll(&mut read) {
+                    Some(Saved::Session(n)) => Some(n),
+                    None => None,
+                    other => panic!("{:?} saved as {:?}", text, other),
+                }
+            })
+            .collect();
+        
+        assert_eq!(saved, [Some(0), None, None, Some(1), None, None, Some(2), None]);
+        // The second "one" replaced the first, rather than duplicating it.
+        assert_eq!(saved_clips(&dir), [(1, "two".to_owned()), (2, "one".to_owned())]);
+    }
+    
+    #[test]
+    fn unreadable_clips_keep_their_metadata() {
+        let dir = test_dir("unreadable");
+        // Something's there, but it can't be read as a clip.
+        std::fs::create_dir(dir.join("0")).unwrap();
+        let mut index = Index::load(&dir);
+        let mut meta = ClipMeta::new(Selection::Clipboard, "image/png", 11);
+        meta.label = Some("logo".to_owned());
+        index.insert(0, meta);
+        index.insert(1, ClipMeta::default());
+        index.save().unwrap();
+        
+        let _ = read_entries(&dir, &mut Index::load(&dir));
+        let mut index = Index::load(&dir);
+        let meta = index.get_mut(0).expect("metadata of unreadable clip was dropped");
+        assert_eq!(meta.label.as_deref(), Some("logo"));
+        assert_eq!(meta.mime, "image/png");
+        // Clip 1 has no file, though, so it's gone.
+        assert!(index.get_mut(1).is_none());
+    }
+    
+    #[test]
+    fn simultaneous_saves_dont_collide() {
+        let dir = test_dir("simultaneous");
+        let save = |name: &'static str| {
+            let dir = dir.to_path_buf();
+            std::thread::spawn(move || {
+                for i in 0..20 {
+                    let text = format!("{} {}", name, i);
+                    let clip = Clip { mime: mime::TEXT_PLAIN.to_owned(), bytes: text.into_bytes() };
+                    save_clip(&dir, Selection::Clipboard, &clip, None).unwrap();
+                }
+            })
+        };
+        let (a, b) = (save("a"), save("b"));
+        a.join().unwrap();
+        b.join().unwrap();
+        
+        let clips = saved_clips(&dir);
+        assert_eq!(clips.len(), 40);
+        for name in ["a", "b"] {
+            for i in 0..20 {
+                let text = format!("{} {}", name, i);
+                assert!(clips.iter().any(|(_, t)| *t == text), "{:?} was lost", text);
+            }
+        }
+    }
+    
+    #[test]
+    fn alpha_sort_ignores_case_and_keeps_pins_first() {
+        let _dir = test_dir("alpha");
+        let entry = |n: usize, text: &str, pinned: bool| Entry {
+            path: Utf8PathBuf::from(n.to_string()),
+            n,
+            contents: text.as_bytes().to_vec(),
+            meta: ClipMeta { pinned, created: n as u64, ..Default::default() },
+            kept: false,
+        };
+        let mut entries = vec![
+            entry(0, "beta", false),
+            entry(1, "Alpha", false),
+            entry(2, "zulu", true),
+            entry(3, "alpha", false),
+            entry(4, "Gamma", false),
+        ];
+        sort_for_menu(&mut entries, SortMode::Alpha);
+        let order: Vec<usize> = entries.iter().map(|ent| ent.n).collect();
+        // Ties go to the newest clip.
+        assert_eq!(order, [2, 3, 1, 0, 4]);
+    }
+    
+    #[test]
+    fn control_characters_get_placeholders() {
+        assert_eq!(sanitize_for_menu("a\0b"), "a\u{2400}b");
+        assert_eq!(sanitize_for_menu("\x1b[31mred\x1b[0m"), "\u{241b}[31mred\u{241b}[0m");
+        assert_eq!(sanitize_for_menu("del\x7f"), "del\u{2421}");
+        assert_eq!(sanitize_for_menu("c1\u{85}\u{9b}"), "c1\u{fffd}\u{fffd}");
+        assert_eq!(sanitize_for_menu("evil\u{202e}txt.exe"), "evil\u{fffd}txt.exe");
+        assert_eq!(sanitize_for_menu("\u{2066}isolate\u{2069}"), "\u{fffd}isolate\u{fffd}");
+        assert_eq!(sanitize_for_menu("plain ünïcode ✓"), "plain ünïcode ✓");
+    }
+    
+    #[test]
+    fn collapsed_text_is_cut_with_an_ellipsis() {
+        assert_eq!(collapse_whitespace("abcd", 4), "abcd");
+        assert_eq!(collapse_whitespace("abcde", 4), "abc\u{2026}");
+        assert_eq!(collapse_whitespace(" a  b ", 3), "a b");
+        assert_eq!(collapse_whitespace("a b", 2), "a\u{2026}");
+        assert_eq!(collapse_whitespace("a \n b", 2), "a\u{2026}");
+    }
+    
+    #[test]
+    fn collapsed_text_gets_placeholders() {
+        // Whitespace controls are collapsed into spaces first.
+        let collapsed = collapse_whitespace("  a\t\0\n\x1b[1mb\u{202e}c  ", 120);
+        assert_eq!(sanitize_for_menu(&collapsed), "a \u{2400} \u{241b}[1mb\u{fffd}c");
+        // Each replaced character still counts as one toward the length.
+        let collapsed = collapse_whitespace("\0\0\0\0\0\0", 4);
+        assert_eq!(sanitize_for_menu(&collapsed), "\u{2400}\u{2400}\u{2400}\u{2026}");
+        
+        let dir = test_dir("placeholders");
+        let mut ent = Entry {
+            path: dir.join("0"),
+            n: 0,
+            contents: b"\x1b[31mred\x1b[0m\0".to_vec(),
+            meta: ClipMeta::default(),
+            kept: false,
+        };
+        assert_eq!(ent.preview(120), "\u{241b}[31mred\u{241b}[0m\u{2400}");
+        // Content types come from the selection's owner, too.
+        ent.meta.mime = "image/x\x1b]0;title\x07".to_owned();
+        assert_eq!(ent.preview(120), "[image/x\u{241b}]0;title\u{2407} 13B]");
+    }
+    
+    #[test]
+    fn values_already_kept_arent_saved_again() {
+        let dir = test_dir("already-kept");
+        let kept_dir = CFG.get().unwrap().persist_dir.as_path();
+        let clip = Clip { mime: mime::TEXT_PLAIN.to_owned(), bytes: b"kept for good".to_vec() };
+        let n = {
+            let _lock = lock::hold(kept_dir).unwrap();
+            let mut index = Index::load(kept_dir);
+            let n = next_n(kept_dir, &read_entries(kept_dir, &mut index).unwrap());
+            write_clip_file_n(kept_dir, n, &clip.bytes).unwrap();
+            index.insert(n, ClipMeta::new(Selection::Clipboard, &clip.mime, clip.bytes.len()));
+            index.save().unwrap();
+            n
+        };
+        
+        let saved = save_clip(&dir, Selection::Clipboard, &clip, None).unwrap();
+        assert_eq!(saved, Some(Saved::Kept(n)));
+        assert_eq!(saved_clips(&dir), []);
+        
+        let _lock = lock::hold(kept_dir).unwrap();
+        std::fs::remove_file(kept_dir.join(n.to_string())).unwrap();
+    }
+    
+    #[test]
+    fn unknown_operations_are_rejected() {
+        let parse = |args: &[&str]| Opts::parse(args.iter().map(|a| a.to_string()));
+        assert!(parse(&["--list", "--json"]).is_ok());
+        assert!(parse(&["--help"]).is_ok());
+        let err = parse(&["--lsit"]).err().unwrap();
+        assert!(err.starts_with("Unknown operation: --lsit\n"));
+        assert!(parse(&[]).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+/*!
+Handling of clip content types.
+
+Text clips are saved under the generic "text/plain" type, which is read
+and written with the backend's ordinary text operations. Anything else
+(images, HTML, URI lists) is saved and recalled under its MIME target.
+*/
+
+// The content type of ordinary text clips.
+pub const TEXT_PLAIN: &str = "text/plain";
+
+/*
+Targets X clients commonly offer for plain text. If any of these is on
+offer, "text/plain" counts as available.
+*/
+const TEXT_TARGETS: &[&str] = &[
+    "UTF8_STRING",
+    "STRING",
+    "TEXT",
+    "COMPOUND_TEXT",
+    "text/plain",
+    "text/plain;charset=utf-8",
+];
+
+// Default order of preference for saved targets.
+pub const DEFAULT_PREFERENCES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/bmp",
+    "image/webp",
+    TEXT_PLAIN,
+];
+
+/*
+Return whether clips of type `mime` should be displayed as text.
+*/
+pub fn is_text(mime: &str) -> bool {
+    mime.starts_with("text/")
+}
+
+/*
+Return the first type in `prefs` that is available among the `offered`
+targets, or "text/plain" if none of them is (or if the targets are
+unknown).
+*/
+pub fn choose_target<'a>(prefs: &'a [String], offered: &[String]) -> &'a str {
+    for pref in prefs.iter() {
+        let available = if pref == TEXT_PLAIN {
+            offered.iter().any(|t| TEXT_TARGETS.contains(&t.as_str()))
+        } else {
+            offered.iter().any(|t| t == pref)
+        };
+        if available {
+            return pref;
+        }
+    }
+    TEXT_PLAIN
+}
+
+/*
+Return a short human-readable rendering of a byte count, like "42KB".
+*/
+pub fn human_size(n: usize) -> String {
+    if n < 1024 {
+        format!("{}B", n)
+    } else if n < 1024 * 1024 {
+        format!("{}KB", (n + 512) / 1024)
+    } else {
+        format!("{:.1}MB", n as f64 / (1024.0 * 1024.0))
+    }
+}
+
+/*
+Return a one-line description of non-text content for the dmenu list,
+like "[image/png 640x480 42KB]".
+*/
+pub fn describe(mime: &str, bytes: &[u8]) -> String {
+    let size = human_size(bytes.len());
+    match image_dimensions(bytes) {
+        Some((w, h)) => format!("[{} {}x{} {}]", mime, w, h, size),
+        None => format!("[{} {}]", mime, size),
+    }
+}
+
+fn be_u16(b: &[u8]) -> u32 { ((b[0] as u32) << 8) | (b[1] as u32) }
+fn le_u16(b: &[u8]) -> u32 { (b[0] as u32) | ((b[1] as u32) << 8) }
+fn be_u32(b: &[u8]) -> u32 { (be_u16(&b[0..2]) << 16) | be_u16(&b[2..4]) }
+fn le_u32(b: &[u8]) -> u32 { le_u16(&b[0..2]) | (le_u16(&b[2..4]) << 16) }
+
+/*
+Return the width and height of a PNG, GIF, BMP, or JPEG image by reading
+its header, or `None` if `bytes` isn't one of those.
+*/
+pub fn image_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") && bytes.len() >= 24 {
+        Some((be_u32(&bytes[16..20]), be_u32(&bytes[20..24])))
+    } else if bytes.starts_with(b"GIF8") && bytes.len() >= 10 {
+        Some((le_u16(&bytes[6..8]), le_u16(&bytes[8..10])))
+    } else if bytes.starts_with(b"BM") && bytes.len() >= 26 {
+        // Height is negative for top-down bitmaps.
+        let h = le_u32(&bytes[22..26]) as i32;
+        Some((le_u32(&bytes[18..22]), h.unsigned_abs()))
+    } else if bytes.starts_with(b"\xff\xd8") {
+        jpeg_dimensions(bytes)
+    } else {
+        None
+    }
+}
+
+/*
+Walk the JPEG marker segments looking for a start-of-frame marker, which
+holds the image dimensions.
+*/
+fn jpeg_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let mut i = 2;
+    while i + 9 < bytes.len() {
+        if bytes[i] != 0xff {
+            return None;
+        }
+        let marker = bytes[i + 1];
+        let is_sof = matches!(marker, 0xc0..=0xcf) && !matches!(marker, 0xc4 | 0xc8 | 0xcc);
+        if is_sof {
+            let h = be_u16(&bytes[i + 5..i + 7]);
+            let w = be_u16(&bytes[i + 7..i + 9]);
+            return Some((w, h));
+        }
+        i += 2 + be_u16(&bytes[i + 2..i + 4]) as usize;
+    }
+    None
+}
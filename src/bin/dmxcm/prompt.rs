@@ -0,0 +1,42 @@
+/*!
+Free-text input through dmenu.
+
+`Dmx::select()` only chooses among items, so for things the user has to
+type (like tags) dmenu is run directly. Whatever is typed is returned;
+any suggestions offered can be picked, or completed with Tab and edited.
+*/
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use camino::Utf8Path;
+
+/*
+Run `dmenu` with the given `prompt`, offering `suggestions`, and return
+the text entered, or `None` if the user cancelled.
+*/
+pub fn text(dmenu: &Utf8Path, prompt: &str, suggestions: &[String]) -> Result<Option<String>, String> {
+    let mut child = Command::new(dmenu)
+        .args(["-p", prompt])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Error running \"{}\": {}", dmenu, &e))?;
+
+    {
+        let mut stdin = child.stdin.take()
+            .ok_or_else(|| format!("Unable to write to \"{}\".", dmenu))?;
+        for line in suggestions.iter() {
+            writeln!(stdin, "{}", line)
+                .map_err(|e| format!("Error writing to \"{}\": {}", dmenu, &e))?;
+        }
+    }
+
+    let output = child.wait_with_output()
+        .map_err(|e| format!("Error reading from \"{}\": {}", dmenu, &e))?;
+    // dmenu exits unsuccessfully when Escape is pressed.
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let text = String::from_utf8_lossy(&output.stdout);
+    Ok(Some(text.trim_end_matches('\n').to_owned()))
+}
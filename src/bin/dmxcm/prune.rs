@@ -0,0 +1,90 @@
+/*!
+Limits on the size of the clip history.
+
+Pruning only decides which clips have to go; actually removing them is
+up to the caller.
+*/
+use std::fmt;
+
+use crate::index::ClipMeta;
+
+/*
+The limits set by the `max_clips`, `max_total_bytes`, and `max_age`
+configuration options. Any of them may be absent.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    pub max_clips: Option<usize>,
+    pub max_total_bytes: Option<u64>,
+    // In seconds.
+    pub max_age: Option<u64>,
+}
+
+/*
+Why a clip was chosen for removal.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    Age,
+    Count,
+    Bytes,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let desc = match self {
+            Reason::Age => "older than max_age",
+            Reason::Count => "more than max_clips",
+            Reason::Bytes => "over max_total_bytes",
+        };
+        write!(f, "{}", desc)
+    }
+}
+
+impl Policy {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_clips.is_none() && self.max_total_bytes.is_none() && self.max_age.is_none()
+    }
+
+    /*
+    Given the numbers and metadata of all saved clips, return the numbers
+    of those that should be removed (and why), as of time `now`.
+
+    Clips older than `max_age` go first; then, working from the newest
+    clip back, everything past the `max_clips`-th clip or past the point
+    where the total size would exceed `max_total_bytes`. Pinned clips are
+    never removed and don't count against the limits.
+    */
+    pub fn victims(&self, clips: &[(usize, &ClipMeta)], now: u64) -> Vec<(usize, Reason)> {
+        let mut victims: Vec<(usize, Reason)> = Vec::new();
+        let mut kept: Vec<(usize, &ClipMeta)> = Vec::with_capacity(clips.len());
+
+        for (n, meta) in clips.iter().filter(|(_, meta)| !meta.pinned) {
+            match self.max_age {
+                Some(age) if now.saturating_sub(meta.created) > age => {
+                    victims.push((*n, Reason::Age));
+                },
+                _ => { kept.push((*n, *meta)); },
+            }
+        }
+
+        kept.sort_unstable_by_key(|(n, _)| std::cmp::Reverse(*n));
+        let mut total_bytes: u64 = 0;
+        let mut cut: Option<Reason> = None;
+        for (i, (n, meta)) in kept.iter().enumerate() {
+            total_bytes += meta.size as u64;
+            if cut.is_none() {
+                if matches!(self.max_clips, Some(max) if i >= max) {
+                    cut = Some(Reason::Count);
+                } else if matches!(self.max_total_bytes, Some(max) if total_bytes > max) {
+                    cut = Some(Reason::Bytes);
+                }
+            }
+            if let Some(reason) = cut {
+                victims.push((*n, reason));
+            }
+        }
+
+        victims
+    }
+}
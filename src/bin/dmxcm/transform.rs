@@ -0,0 +1,353 @@
+/*!
+Transformations `--transform` can apply to a text clip on its way to the
+clipboard.
+
+The built-in ones are listed in `Builtin::ALL`; the user can add more as
+external filter commands (`[filters]` in `dmxcm.toml`), which get the
+text on stdin and write the result to stdout.
+*/
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use dm_x::Item;
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+// Matches ANSI escape sequences: CSI (like colors), OSC (like titles), and
+// two-character escapes.
+static ANSI_RE: Lazy<Regex> = Lazy::new(|| Regex::new(
+    r"\x1b(\[[0-?]*[ -/]*[@-~]|\][^\x07\x1b]*(\x07|\x1b\\)|[@-Z\\-_])"
+).unwrap());
+
+const BASE64_CHARS: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+    Trim,
+    Collapse,
+    Upper,
+    Lower,
+    Title,
+    UrlEncode,
+    UrlDecode,
+    Base64Encode,
+    Base64Decode,
+    JsonPretty,
+    JsonMinify,
+    ShellQuote,
+    StripAnsi,
+}
+
+impl Builtin {
+    // In the order they're offered in the menu.
+    pub const ALL: &'static [Builtin] = &[
+        Builtin::Trim,
+        Builtin::Collapse,
+        Builtin::Upper,
+        Builtin::Lower,
+        Builtin::Title,
+        Builtin::UrlEncode,
+        Builtin::UrlDecode,
+        Builtin::Base64Encode,
+        Builtin::Base64Decode,
+        Builtin::JsonPretty,
+        Builtin::JsonMinify,
+        Builtin::ShellQuote,
+        Builtin::StripAnsi,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Builtin::Trim => "trim",
+            Builtin::Collapse => "collapse whitespace",
+            Builtin::Upper => "upper case",
+            Builtin::Lower => "lower case",
+            Builtin::Title => "title case",
+            Builtin::UrlEncode => "url encode",
+            Builtin::UrlDecode => "url decode",
+            Builtin::Base64Encode => "base64 encode",
+            Builtin::Base64Decode => "base64 decode",
+            Builtin::JsonPretty => "json pretty",
+            Builtin::JsonMinify => "json minify",
+            Builtin::ShellQuote => "shell quote",
+            Builtin::StripAnsi => "strip ansi codes",
+        }
+    }
+
+    pub fn apply(&self, text: &str) -> Result<String, String> {
+        match self {
+            Builtin::Trim => Ok(text.trim().to_owned()),
+            Builtin::Collapse => Ok(crate::collapse_whitespace(text, usize::MAX)),
+            Builtin::Upper => Ok(text.to_uppercase()),
+            Builtin::Lower => Ok(text.to_lowercase()),
+            Builtin::Title => Ok(title_case(text)),
+            Builtin::UrlEncode => Ok(url_encode(text)),
+            Builtin::UrlDecode => url_decode(text),
+            Builtin::Base64Encode => Ok(base64_encode(text.as_bytes())),
+            Builtin::Base64Decode => {
+                let bytes = base64_decode(text)?;
+                String::from_utf8(bytes)
+                    .map_err(|_| "Decoded base64 isn't UTF-8 text.".to_owned())
+            },
+            Builtin::JsonPretty | Builtin::JsonMinify => {
+                let value: serde_json::Value = serde_json::from_str(text)
+                    .map_err(|e| format!("Clip isn't valid JSON: {}", &e))?;
+                let out = if *self == Builtin::JsonPretty {
+                    serde_json::to_string_pretty(&value)
+                } else {
+                    serde_json::to_string(&value)
+                };
+                out.map_err(|e| format!("Error serializing JSON: {}", &e))
+            },
+            Builtin::ShellQuote => Ok(shell_quote(text)),
+            Builtin::StripAnsi => Ok(ANSI_RE.replace_all(text, "").into_owned()),
+        }
+    }
+}
+
+/*
+A transformation offered in the menu: built in, or a user's filter
+command.
+*/
+#[derive(Debug, Clone)]
+pub enum Transform {
+    Builtin(Builtin),
+    Filter { name: String, command: Vec<String> },
+}
+
+impl Transform {
+    pub fn name(&self) -> &str {
+        match self {
+            Transform::Builtin(b) => b.name(),
+            Transform::Filter { name, .. } => name,
+        }
+    }
+
+    pub fn apply(&self, text: &str) -> Result<String, String> {
+        match self {
+            Transform::Builtin(b) => b.apply(text),
+            Transform::Filter { name, command } => run_filter(name, command, text),
+        }
+    }
+}
+
+impl Item for Transform {
+    fn key_len(&self) -> usize {
+        self.name().chars().count()
+    }
+
+    fn line(&self, _key_len: usize) -> Vec<u8> {
+        match self {
+            Transform::Builtin(_) => self.name().as_bytes().to_vec(),
+            Transform::Filter { name, command } => {
+                format!("{}  ({})", name, command.join(" ")).into_bytes()
+            },
+        }
+    }
+}
+
+/*
+Pipe `text` through the external `command` and return what it writes to
+stdout.
+*/
+fn run_filter(name: &str, command: &[String], text: &str) -> Result<String, String> {
+    let (prog, args) = command.split_first()
+        .ok_or_else(|| format!("Filter \"{}\" has an empty command.", name))?;
+    let mut child = Command::new(prog)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Unable to run filter \"{}\": {}", name, &e))?;
+
+    // Write from another thread so a filter that starts writing before it's
+    // read everything can't deadlock us.
+    let mut stdin = child.stdin.take()
+        .ok_or_else(|| format!("Filter \"{}\" stdin handle unavailable.", name))?;
+    let input = text.as_bytes().to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+
+    let output = child.wait_with_output()
+        .map_err(|e| format!("Error awaiting filter \"{}\": {}", name, &e))?;
+    if let Ok(Err(e)) = writer.join() {
+        return Err(format!("Error writing to filter \"{}\": {}", name, &e));
+    }
+    if !output.status.success() {
+        return Err(format!("Filter \"{}\" returned exit code {:?}", name, &output.status.code()));
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|_| format!("Output of filter \"{}\" isn't UTF-8 text.", name))
+}
+
+/*
+Capitalize the first letter of each word and lower-case the rest.
+*/
+fn title_case(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut at_word_start = true;
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            if at_word_start {
+                out.extend(c.to_uppercase());
+            } else {
+                out.extend(c.to_lowercase());
+            }
+            at_word_start = false;
+        } else {
+            out.push(c);
+            at_word_start = !(c == '\'' || c == '\u{2019}');
+        }
+    }
+    out
+}
+
+/*
+Percent-encode everything but the unreserved characters of RFC 3986.
+*/
+fn url_encode(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for b in text.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/*
+Decode percent escapes (and '+' as a space, as in query strings).
+*/
+fn url_decode(text: &str) -> Result<String, String> {
+    let bytes = text.as_bytes();
+    let mut out: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3)
+                    .and_then(|h| std::str::from_utf8(h).ok())
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                    .ok_or_else(|| format!("Bad percent escape at position {}.", i))?;
+                out.push(hex);
+                i += 3;
+            },
+            b'+' => {
+                out.push(b' ');
+                i += 1;
+            },
+            b => {
+                out.push(b);
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8(out).map_err(|_| "Decoded URL isn't UTF-8 text.".to_owned())
+}
+
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | (b[2] as u32);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_CHARS[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/*
+Decode standard or URL-safe base64, ignoring whitespace and padding. A
+lone character left over at the end (which can't hold a whole byte) is
+an error.
+*/
+pub fn base64_decode(text: &str) -> Result<Vec<u8>, String> {
+    let mut out: Vec<u8> = Vec::with_capacity(text.len() * 3 / 4);
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace() && *c != b'=') {
+        let val = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => { return Err(format!("'{}' isn't a base64 character.", c as char)); },
+        };
+        acc = (acc << 6) | val as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    if bits == 6 {
+        return Err("Invalid base64 length.".to_owned());
+    }
+    Ok(out)
+}
+
+/*
+Quote `text` for a POSIX shell: wrap it in single quotes, with any single
+quotes inside written as '\''.
+*/
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_encoding_round_trips() {
+        let text = "a b&c=d/é~_.-+%";
+        let encoded = url_encode(text);
+        assert_eq!(encoded, "a%20b%26c%3Dd%2F%C3%A9~_.-%2B%25");
+        assert_eq!(url_decode(&encoded).unwrap(), text);
+        assert_eq!(url_decode("a+b%2").err().unwrap(), "Bad percent escape at position 3.");
+    }
+
+    #[test]
+    fn base64_round_trips() {
+        for (plain, encoded) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(base64_encode(plain.as_bytes()), encoded);
+            assert_eq!(base64_decode(encoded).unwrap(), plain.as_bytes());
+        }
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(base64_decode(&base64_encode(&bytes)).unwrap(), bytes);
+        // URL-safe, unpadded, and wrapped input all decode.
+        assert_eq!(base64_decode("-_8\n").unwrap(), [0xfb, 0xff]);
+    }
+
+    #[test]
+    fn base64_with_a_dangling_character_is_rejected() {
+        assert_eq!(base64_decode("Zm9vY").err().unwrap(), "Invalid base64 length.");
+        assert_eq!(base64_decode("Z===").err().unwrap(), "Invalid base64 length.");
+    }
+
+    #[test]
+    fn json_keys_keep_their_order() {
+        let text = r#"{"b": 1, "a": {"z": true, "y": null}}"#;
+        assert_eq!(
+            Builtin::JsonMinify.apply(text).unwrap(),
+            r#"{"b":1,"a":{"z":true,"y":null}}"#
+        );
+        assert!(Builtin::JsonPretty.apply(text).unwrap().starts_with("{\n  \"b\": 1,"));
+    }
+}
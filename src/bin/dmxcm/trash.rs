@@ -0,0 +1,170 @@
+/*!
+The trash: where deleted clips wait a while before they're gone for good.
+
+Each clip directory has its own hidden `.trash` subdirectory. A trashed
+clip's file is moved there as-is (so an encrypted clip stays encrypted)
+under the name `DELETED-N`, where DELETED is when it was deleted and N is
+the number it had; its index metadata goes alongside in `DELETED-N.json`.
+*/
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::index::ClipMeta;
+
+// Name of the trash directory inside a clip directory.
+const TRASH_DIR: &str = ".trash";
+
+/*
+What's kept in the `.json` file next to a trashed clip.
+*/
+#[derive(Serialize, Deserialize)]
+struct Record {
+    n: usize,
+    deleted: u64,
+    #[serde(flatten)]
+    meta: ClipMeta,
+}
+
+/*
+A clip in the trash.
+*/
+pub struct Trashed {
+    // The trashed clip file.
+    pub path: Utf8PathBuf,
+    // The number it had before it was deleted.
+    pub n: usize,
+    // When it was deleted.
+    pub deleted: u64,
+    pub meta: ClipMeta,
+}
+
+fn record_path(path: &Utf8Path) -> Utf8PathBuf {
+    path.with_extension("json")
+}
+
+/*
+Move the file of clip `n` (at `path`, in clip directory `dir`) into the
+trash, along with its metadata, noting that it was deleted at `now`.
+*/
+pub fn put(dir: &Utf8Path, n: usize, path: &Utf8Path, meta: &ClipMeta, now: u64) -> Result<(), String> {
+    let trash_dir = dir.join(TRASH_DIR);
+    std::fs::create_dir_all(&trash_dir)
+        .map_err(|e| format!("Unable to create trash directory \"{}\": {}", &trash_dir, &e))?;
+
+    let dest = trash_dir.join(format!("{}-{}", now, n));
+    let record = Record { n, deleted: now, meta: meta.clone() };
+    let bytes = serde_json::to_vec_pretty(&record)
+        .map_err(|e| format!("Error serializing trash record: {}", &e))?;
+    std::fs::write(record_path(&dest), &bytes)
+        .map_err(|e| format!("Error writing trash record for \"{}\": {}", &dest, &e))?;
+    std::fs::rename(path, &dest)
+        .map_err(|e| format!("Unable to move \"{}\" to the trash: {}", path, &e))
+}
+
+/*
+Return the clips in the trash of clip directory `dir`, most recently
+deleted first.
+*/
+pub fn list(dir: &Utf8Path) -> Result<Vec<Trashed>, String> {
+    let trash_dir = dir.join(TRASH_DIR);
+    let read = match trash_dir.read_dir_utf8() {
+        Ok(read) => read,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => { return Ok(Vec::new()); },
+        Err(e) => {
+            return Err(format!("Unable to read trash directory \"{}\": {}", &trash_dir, &e));
+        },
+    };
+
+    let mut trashed: Vec<Trashed> = Vec::new();
+    for ent in read.flatten() {
+        let path = ent.path();
+        if path.extension() == Some("json") {
+            continue;
+        }
+        let record: Record = match std::fs::read(record_path(path))
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| serde_json::from_slice(&bytes).map_err(|e| e.to_string()))
+        {
+            Ok(record) => record,
+            Err(e) => {
+                eprintln!("Unable to read trash record for \"{}\": {}", path, &e);
+                continue;
+            },
+        };
+        trashed.push(Trashed {
+            path: path.to_path_buf(),
+            n: record.n,
+            deleted: record.deleted,
+            meta: record.meta,
+        });
+    }
+
+    trashed.sort_unstable_by_key(|t| std::cmp::Reverse(t.deleted));
+    Ok(trashed)
+}
+
+impl Trashed {
+    /*
+    Move the clip back out of the trash to `dest`, discarding its record.
+    */
+    pub fn restore(&self, dest: &Utf8Path) -> Result<(), String> {
+        std::fs::rename(&self.path, dest)
+            .map_err(|e| format!("Unable to restore \"{}\" to \"{}\": {}", &self.path, dest, &e))?;
+        self.forget_record()
+    }
+
+    // Delete the clip and its record for good.
+    pub fn remove(&self) -> Result<(), String> {
+        std::fs::remove_file(&self.path)
+            .map_err(|e| format!("Error removing trashed clip \"{}\": {}", &self.path, &e))?;
+        self.forget_record()
+    }
+
+    fn forget_record(&self) -> Result<(), String> {
+        let path = record_path(&self.path);
+        std::fs::remove_file(&path)
+            .map_err(|e| format!("Error removing trash record \"{}\": {}", &path, &e))
+    }
+}
+
+/*
+Permanently delete everything in the trash of `dir` that was deleted
+at least `retention` seconds before `now`, or whose lifetime (from
+`--ttl`) has run out since. Returns how many clips went.
+*/
+pub fn purge(dir: &Utf8Path, retention: u64, now: u64) -> Result<usize, String> {
+    let mut count = 0;
+    for t in list(dir)?.iter()
+        .filter(|t| now.saturating_sub(t.deleted) >= retention || t.meta.is_expired(now))
+    {
+        t.remove()?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expired_clips_are_purged_before_their_retention_is_up() {
+        let dir = Utf8PathBuf::from_path_buf(std::env::temp_dir())
+            .unwrap()
+            .join(format!("dmxcm-test-trash-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (n, expires) in [(0, None), (1, Some(150)), (2, Some(250))] {
+            let path = dir.join(n.to_string());
+            std::fs::write(&path, "clip").unwrap();
+            let meta = ClipMeta { expires, ..ClipMeta::default() };
+            put(&dir, n, &path, &meta, 100).unwrap();
+        }
+
+        assert_eq!(purge(&dir, 1000, 200).unwrap(), 1);
+        let mut left: Vec<usize> = list(&dir).unwrap().iter().map(|t| t.n).collect();
+        left.sort_unstable();
+        assert_eq!(left, [0, 2]);
+        assert_eq!(purge(&dir, 1000, 1100).unwrap(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,90 @@
+/*!
+Recalling clips by typing them into the focused window.
+
+Some terminals and remote consoles ignore the clipboard, but they'll take
+keystrokes. This drives `xdotool` (under X) or `wtype` (under Wayland) to
+type a clip out; the clipboard itself is never touched.
+*/
+use std::process::Command;
+
+use camino::Utf8PathBuf;
+
+use crate::clipboard::pipe_into;
+
+/*
+The value of the `type_backend = ` configuration option.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Auto,
+    Xdotool,
+    Wtype,
+}
+
+impl Backend {
+    pub fn from_name(name: &str) -> Result<Backend, String> {
+        match name {
+            "auto" => Ok(Backend::Auto),
+            "xdotool" => Ok(Backend::Xdotool),
+            "wtype" => Ok(Backend::Wtype),
+            _ => Err(format!(
+                "Unknown typing backend \"{}\"; expected one of \"auto\", \"xdotool\", or \"wtype\".",
+                name
+            )),
+        }
+    }
+
+    /*
+    Resolve `Backend::Auto`: wtype under Wayland, xdotool otherwise.
+    */
+    pub fn detect(self) -> Backend {
+        if self != Backend::Auto {
+            return self;
+        }
+        match std::env::var_os("WAYLAND_DISPLAY") {
+            Some(val) if !val.is_empty() => Backend::Wtype,
+            _ => Backend::Xdotool,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Typist {
+    pub backend: Backend,
+    pub xdotool: Utf8PathBuf,
+    pub wtype: Utf8PathBuf,
+    // Delay between keystrokes, in milliseconds.
+    pub delay: u64,
+}
+
+impl Default for Typist {
+    fn default() -> Typist {
+        Typist {
+            backend: Backend::Auto,
+            xdotool: "xdotool".into(),
+            wtype: "wtype".into(),
+            delay: 12,
+        }
+    }
+}
+
+impl Typist {
+    /*
+    Type `text` into whatever window has the keyboard focus.
+    */
+    pub fn type_text(&self, text: &[u8]) -> Result<(), String> {
+        let delay = self.delay.to_string();
+        match self.backend.detect() {
+            Backend::Xdotool | Backend::Auto => {
+                let mut cmd = Command::new(&self.xdotool);
+                cmd.args(["type", "--clearmodifiers", "--delay", &delay, "--file", "-"]);
+                pipe_into("xdotool", cmd, text)
+            },
+            Backend::Wtype => {
+                let mut cmd = Command::new(&self.wtype);
+                cmd.args(["-d", &delay, "-"]);
+                pipe_into("wtype", cmd, text)
+            },
+        }
+    }
+}
@@ -18,3 +18,20 @@ pub fn config_directory() -> Result<Utf8PathBuf, &'static str> {
         }
     }
 }
+
+pub fn data_directory() -> Result<Utf8PathBuf, &'static str> {
+    use std::env::var;
+    
+    match var("XDG_DATA_HOME") {
+        Ok(p) => Ok(Utf8PathBuf::from(p)),
+        Err(_) => match var("HOME") {
+            Ok(home) => {
+                let mut pbuff = Utf8PathBuf::from(home);
+                pbuff.push(".local");
+                pbuff.push("share");
+                Ok(pbuff)
+            },
+            Err(_) => Err("Unable to determine data directory.")
+        }
+    }
+}